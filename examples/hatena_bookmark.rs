@@ -5,7 +5,7 @@ fn main() {
         r#"
 <div class="entrylist-contents-main">
     <h3 class="entrylist-contents-title">
-        <a href="{{url}}" title="{{title}}"></a>
+        <a href="{{url:url}}" title="{{title}}"></a>
     </h3>
     <span class="entrylist-contents-users">
         <a><span>{{users}}</span> users</a>
@@ -32,13 +32,9 @@ fn main() {
         .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/69.0.3497.100")
         .build()
         .unwrap();
-    let doc = client
-        .get("https://b.hatena.ne.jp/hotentry/it")
-        .send()
-        .unwrap()
-        .text()
-        .unwrap();
+    let url = "https://b.hatena.ne.jp/hotentry/it";
+    let doc = client.get(url).send().unwrap().text().unwrap();
 
-    let ms = pat.matches(&doc);
+    let ms = pat.matches_with_base(&doc, url).unwrap();
     println!("{:#?}", ms);
 }
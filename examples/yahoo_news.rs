@@ -9,10 +9,13 @@ fn main() {
 "#,
     )
     .unwrap();
-    let doc = reqwest::blocking::get("https://news.yahoo.co.jp/")
-        .unwrap()
-        .text()
-        .unwrap();
-    let ms = pat.matches(&doc);
+    let res = reqwest::blocking::get("https://news.yahoo.co.jp/").unwrap();
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_owned());
+    let bytes = res.bytes().unwrap();
+    let ms = pat.matches_bytes(&bytes, content_type.as_deref());
     println!("{:#?}", ms);
 }
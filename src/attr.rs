@@ -0,0 +1,147 @@
+// Compound attribute values: `srcset` and inline `style`.
+
+// URL of the largest candidate in `srcset`, by width (`640w`) or density (`2x`).
+pub(crate) fn largest_srcset(s: &str) -> Option<&str> {
+    let mut best: Option<(&str, (f64, f64))> = None;
+    for (url, descriptors) in parse_srcset(s) {
+        let mut size = (0.0, 1.0);
+        for d in descriptors.split_whitespace() {
+            if let Some(w) = d.strip_suffix('w').and_then(|r| r.parse().ok()) {
+                size.0 = w;
+            } else if let Some(x) = d.strip_suffix('x').and_then(|r| r.parse().ok()) {
+                size.1 = x;
+            }
+        }
+        if best.is_none_or(|(_, b)| size > b) {
+            best = Some((url, size));
+        }
+    }
+    best.map(|(url, _)| url)
+}
+
+// Pairs of a URL and its descriptors.
+// URLs can contain commas, so candidates are not simply split by commas.
+fn parse_srcset(s: &str) -> Vec<(&str, &str)> {
+    let mut ret = vec![];
+    let mut rest = s;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        if rest.is_empty() {
+            break;
+        }
+
+        let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let url = &rest[..len];
+        rest = &rest[len..];
+
+        // A URL ending with commas has no descriptors.
+        let trimmed = url.trim_end_matches(',');
+        if trimmed.len() < url.len() {
+            ret.push((trimmed, ""));
+            continue;
+        }
+
+        let len = rest.find(',').unwrap_or(rest.len());
+        ret.push((url, rest[..len].trim()));
+        rest = &rest[len..];
+    }
+    ret
+}
+
+// Declarations of inline style, with lowercased property names.
+// Quotes in `url("...")` are removed, so that `url({{x}})` captures the URL.
+pub(crate) fn parse_style(s: &str) -> Vec<(String, String)> {
+    split_declarations(s)
+        .into_iter()
+        .filter_map(|decl| {
+            let (name, value) = decl.split_once(':')?;
+            let name = name.trim().to_ascii_lowercase();
+            if name.is_empty() {
+                return None;
+            }
+            Some((name, unquote_urls(value.trim())))
+        })
+        .collect()
+}
+
+// Splits by `;`, except in quotes and parentheses, e.g. `url("data:image/png;base64,...")`.
+fn split_declarations(s: &str) -> Vec<&str> {
+    let mut ret = vec![];
+    let mut quote = None;
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth > 0 => depth -= 1,
+            (None, ';') if depth == 0 => {
+                ret.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    ret.push(&s[start..]);
+    ret
+}
+
+fn unquote_urls(s: &str) -> String {
+    let mut ret = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find("url(") {
+        ret += &rest[..i + 4];
+        rest = &rest[i + 4..];
+        let close = match rest.find(')') {
+            Some(close) => close,
+            None => break,
+        };
+        let url = rest[..close].trim();
+        let url = ['"', '\'']
+            .iter()
+            .find_map(|&q| url.strip_prefix(q)?.strip_suffix(q))
+            .unwrap_or(url);
+        ret += url;
+        rest = &rest[close..];
+    }
+    ret + rest
+}
+
+#[test]
+fn test_attr() {
+    assert_eq!(
+        largest_srcset("a.jpg 480w, b.jpg 1080w, c.jpg 800w"),
+        Some("b.jpg")
+    );
+    assert_eq!(largest_srcset("a.jpg, b.jpg 2x"), Some("b.jpg"));
+    assert_eq!(
+        largest_srcset("data:image/png;base64,AAA 1x, /b,c.jpg 3x"),
+        Some("/b,c.jpg")
+    );
+    // commas without white-space are a part of the URL
+    assert_eq!(largest_srcset("a.jpg,b.jpg"), Some("a.jpg,b.jpg"));
+    assert_eq!(largest_srcset(" "), None);
+
+    assert_eq!(
+        parse_style("Color: red; background-image: url( 'a.jpg' ) , url(\"b.jpg\");;"),
+        vec![
+            ("color".to_owned(), "red".to_owned()),
+            (
+                "background-image".to_owned(),
+                "url(a.jpg) , url(b.jpg)".to_owned()
+            ),
+        ]
+    );
+    assert_eq!(
+        parse_style("background-image: url(\"data:image/png;base64,AAA\"); content: ';'"),
+        vec![
+            (
+                "background-image".to_owned(),
+                "url(data:image/png;base64,AAA)".to_owned()
+            ),
+            ("content".to_owned(), "';'".to_owned()),
+        ]
+    );
+}
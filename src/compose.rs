@@ -0,0 +1,310 @@
+// Patterns built from nodes in Rust, without writing HTML sources.
+// Nodes are converted to the same tree as parsed patterns, so that texts and
+// attribute values need no escaping. Elements in `<svg>` and `<math>` get their
+// namespaces and names as the tree builder adjusts them.
+
+use html5ever::{LocalName, Namespace, Prefix, QualName};
+use kuchiki::{Attribute, ExpandedName, NodeRef};
+
+const SVG_NS: &str = "http://www.w3.org/2000/svg";
+const MATHML_NS: &str = "http://www.w3.org/1998/Math/MathML";
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+const XMLNS_NS: &str = "http://www.w3.org/2000/xmlns/";
+
+// Names of SVG elements and attributes which are not lowercase.
+const SVG_ELEMENTS: &[&str] = &[
+    "altGlyph",
+    "altGlyphDef",
+    "altGlyphItem",
+    "animateColor",
+    "animateMotion",
+    "animateTransform",
+    "clipPath",
+    "feBlend",
+    "feColorMatrix",
+    "feComponentTransfer",
+    "feComposite",
+    "feConvolveMatrix",
+    "feDiffuseLighting",
+    "feDisplacementMap",
+    "feDistantLight",
+    "feDropShadow",
+    "feFlood",
+    "feFuncA",
+    "feFuncB",
+    "feFuncG",
+    "feFuncR",
+    "feGaussianBlur",
+    "feImage",
+    "feMerge",
+    "feMergeNode",
+    "feMorphology",
+    "feOffset",
+    "fePointLight",
+    "feSpecularLighting",
+    "feSpotLight",
+    "feTile",
+    "feTurbulence",
+    "foreignObject",
+    "glyphRef",
+    "linearGradient",
+    "radialGradient",
+    "textPath",
+];
+
+const SVG_ATTRIBUTES: &[&str] = &[
+    "attributeName",
+    "attributeType",
+    "baseFrequency",
+    "baseProfile",
+    "calcMode",
+    "clipPathUnits",
+    "diffuseConstant",
+    "edgeMode",
+    "filterUnits",
+    "glyphRef",
+    "gradientTransform",
+    "gradientUnits",
+    "kernelMatrix",
+    "kernelUnitLength",
+    "keyPoints",
+    "keySplines",
+    "keyTimes",
+    "lengthAdjust",
+    "limitingConeAngle",
+    "markerHeight",
+    "markerUnits",
+    "markerWidth",
+    "maskContentUnits",
+    "maskUnits",
+    "numOctaves",
+    "pathLength",
+    "patternContentUnits",
+    "patternTransform",
+    "patternUnits",
+    "pointsAtX",
+    "pointsAtY",
+    "pointsAtZ",
+    "preserveAlpha",
+    "preserveAspectRatio",
+    "primitiveUnits",
+    "refX",
+    "refY",
+    "repeatCount",
+    "repeatDur",
+    "requiredExtensions",
+    "requiredFeatures",
+    "specularConstant",
+    "specularExponent",
+    "spreadMethod",
+    "startOffset",
+    "stdDeviation",
+    "stitchTiles",
+    "surfaceScale",
+    "systemLanguage",
+    "tableValues",
+    "targetX",
+    "targetY",
+    "textLength",
+    "viewBox",
+    "viewTarget",
+    "xChannelSelector",
+    "yChannelSelector",
+    "zoomAndPan",
+];
+
+// Namespaced attributes of foreign elements: name, prefix, namespace and local name.
+const FOREIGN_ATTRIBUTES: &[(&str, &str, &str, &str)] = &[
+    ("xlink:actuate", "xlink", XLINK_NS, "actuate"),
+    ("xlink:arcrole", "xlink", XLINK_NS, "arcrole"),
+    ("xlink:href", "xlink", XLINK_NS, "href"),
+    ("xlink:role", "xlink", XLINK_NS, "role"),
+    ("xlink:show", "xlink", XLINK_NS, "show"),
+    ("xlink:title", "xlink", XLINK_NS, "title"),
+    ("xlink:type", "xlink", XLINK_NS, "type"),
+    ("xml:lang", "xml", XML_NS, "lang"),
+    ("xml:space", "xml", XML_NS, "space"),
+    ("xmlns", "", XMLNS_NS, "xmlns"),
+    ("xmlns:xlink", "xmlns", XMLNS_NS, "xlink"),
+];
+
+// Namespace of elements.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Content {
+    Html,
+    Svg,
+    MathMl,
+}
+
+// Name in `table` for the lowercase `name`.
+fn adjust(name: &str, table: &[&'static str]) -> String {
+    table
+        .iter()
+        .find(|r| r.eq_ignore_ascii_case(name))
+        .map_or_else(|| name.to_owned(), |r| (*r).to_owned())
+}
+
+/// Node of pattern built in Rust
+///
+/// Texts and attribute values are used as they are, without escaping.
+/// Placeholders are written as `{{name}}` in them.
+///
+/// ```
+/// use easy_scraper::{Node, Pattern};
+///
+/// let pat = Pattern::from_nodes(&[Node::element("tr")
+///     .child(Node::element("th").child(Node::text("<Price>")))
+///     .child(Node::element("td").child(Node::placeholder("price|number")))])
+/// .unwrap();
+///
+/// let ms = pat.matches("<table><tr><th>&lt;Price&gt;</th><td>$100</td></tr></table>");
+/// assert_eq!(ms[0]["price"], "100");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    data: NodeData,
+    children: Vec<Node>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum NodeData {
+    Element(String, Vec<(String, String)>),
+    Text(String),
+}
+
+impl Node {
+    /// Element of `name`, which is lowercased as HTML parser does
+    ///
+    /// Elements in `svg` and `math` get their namespaces, and names such as `clipPath`
+    /// as the parser adjusts them.
+    pub fn element(name: &str) -> Node {
+        Node {
+            data: NodeData::Element(name.to_ascii_lowercase(), vec![]),
+            children: vec![],
+        }
+    }
+
+    /// Text node
+    pub fn text(text: &str) -> Node {
+        Node {
+            data: NodeData::Text(text.to_owned()),
+            children: vec![],
+        }
+    }
+
+    /// Text node of a placeholder, e.g. `Node::placeholder("price|number")` for `{{price|number}}`
+    pub fn placeholder(name: &str) -> Node {
+        Node::text(&format!("{{{{{}}}}}", name))
+    }
+
+    /// `...` between siblings
+    pub fn skip() -> Node {
+        Node::text("...")
+    }
+
+    /// Add attribute, whose name is lowercased as HTML parser does
+    ///
+    /// Attributes of texts are ignored.
+    pub fn attr(mut self, name: &str, value: &str) -> Node {
+        if let NodeData::Element(_, attrs) = &mut self.data {
+            let name = name.to_ascii_lowercase();
+            attrs.retain(|(r, _)| *r != name);
+            attrs.push((name, value.to_owned()));
+        }
+        self
+    }
+
+    /// Match children as subsequence
+    pub fn subseq(self) -> Node {
+        self.attr("subseq", "")
+    }
+
+    /// Add child node
+    pub fn child(mut self, child: Node) -> Node {
+        self.children.push(child);
+        self
+    }
+
+    /// Add child nodes
+    pub fn children<I: IntoIterator<Item = Node>>(mut self, children: I) -> Node {
+        self.children.extend(children);
+        self
+    }
+
+    fn to_node_ref(&self, parent: Content) -> NodeRef {
+        let (name, attrs) = match &self.data {
+            NodeData::Element(name, attrs) => (name, attrs),
+            NodeData::Text(text) => return NodeRef::new_text(text.clone()),
+        };
+
+        let content = match (parent, name.as_str()) {
+            (Content::Html, "svg") => Content::Svg,
+            (Content::Html, "math") => Content::MathMl,
+            _ => parent,
+        };
+        let (ns, local) = match content {
+            Content::Html => (crate::HTML_NS, name.clone()),
+            Content::Svg => (SVG_NS, adjust(name, SVG_ELEMENTS)),
+            Content::MathMl => (MATHML_NS, name.clone()),
+        };
+        let attrs = attrs.iter().map(|(name, value)| {
+            let foreign = FOREIGN_ATTRIBUTES
+                .iter()
+                .find(|r| content != Content::Html && r.0 == name);
+            let (prefix, ns, local) = match (foreign, content) {
+                (Some(&(_, prefix, ns, local)), _) => {
+                    let prefix = Some(prefix).filter(|r| !r.is_empty());
+                    (prefix.map(Prefix::from), ns, local.to_owned())
+                }
+                (None, Content::Svg) => (None, "", adjust(name, SVG_ATTRIBUTES)),
+                (None, Content::MathMl) => (None, "", adjust(name, &["definitionURL"])),
+                (None, Content::Html) => (None, "", name.clone()),
+            };
+            (
+                ExpandedName::new(ns, &*local),
+                Attribute {
+                    prefix,
+                    value: value.clone(),
+                },
+            )
+        });
+        let ret = NodeRef::new_element(
+            QualName::new(None, Namespace::from(ns), LocalName::from(&*local)),
+            attrs.collect::<Vec<_>>(),
+        );
+
+        // Children of integration points are HTML.
+        let children = match (content, local.as_str()) {
+            (Content::Svg, "foreignObject" | "desc" | "title") => Content::Html,
+            (Content::MathMl, "mi" | "mo" | "mn" | "ms" | "mtext") => Content::Html,
+            _ => content,
+        };
+        for child in self.children.iter() {
+            append(&ret, child.to_node_ref(children));
+        }
+        ret
+    }
+}
+
+// Appends `child`, merging adjacent texts into one as the parser does,
+// e.g. `Price: ` and `{{p}}` into `Price: {{p}}`.
+fn append(parent: &NodeRef, child: NodeRef) {
+    if let (Some(last), Some(text)) = (
+        parent.last_child().as_ref().and_then(|r| r.as_text()),
+        child.as_text(),
+    ) {
+        last.borrow_mut().push_str(&text.borrow());
+        return;
+    }
+    parent.append(child);
+}
+
+// Fragment of `nodes`, in the same form as parsed patterns.
+pub(crate) fn to_tree(nodes: &[Node]) -> NodeRef {
+    let ret = NodeRef::new_document();
+    for node in nodes {
+        append(&ret, node.to_node_ref(Content::Html));
+    }
+    ret
+}
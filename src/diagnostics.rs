@@ -0,0 +1,474 @@
+// Warnings for patterns changed by the HTML parser.
+// The source is scanned into a tree of elements as written, which is compared with
+// the pattern built from it. Elements are paired by positions in the trees, and then
+// by names, so that elements moved elsewhere, inserted or dropped by the parser are found.
+
+use crate::pattern::PatternNode;
+use crate::print::is_void_element;
+use kuchiki::NodeRef;
+use std::fmt;
+
+/// Warning about a pattern changed by the HTML parser
+///
+/// Returned by [`Pattern::new_with_diagnostics`](crate::Pattern::new_with_diagnostics).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    /// Line of the node in the source, starting from 1
+    pub line: usize,
+    /// Column of the node in the source in characters, starting from 1
+    pub column: usize,
+    pub message: String,
+}
+
+/// Kind of [`Warning`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WarningKind {
+    /// Element is moved out of a table, e.g. `<div>` directly in `<table>`
+    FosterParented,
+    /// Element is closed before its children, e.g. `<p>` by `<div>` in it
+    AutoClosed,
+    /// Element which is not in the source is inserted, e.g. `<tr>` around `<td>` in `<table>`
+    AutoInserted,
+    /// Element is moved to another parent
+    Reordered,
+    /// Element is ignored, e.g. `<body>` in `<body>`
+    Dropped,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+// Element in the source as written.
+struct SourceElement {
+    name: String,
+    parent: Option<usize>,
+    pos: usize,
+}
+
+// Elements whose end tags can be omitted before the same kind of elements.
+fn closes(open: &str, name: &str) -> bool {
+    match name {
+        "li" => open == "li",
+        "dt" | "dd" => open == "dt" || open == "dd",
+        "option" => open == "option",
+        "td" | "th" => open == "td" || open == "th",
+        "tr" => ["tr", "td", "th"].contains(&open),
+        "thead" | "tbody" | "tfoot" => {
+            ["thead", "tbody", "tfoot", "tr", "td", "th"].contains(&open)
+        }
+        "p" => open == "p",
+        _ => false,
+    }
+}
+
+// Scans start and end tags of the source. End tags without open elements are also
+// returned, with their positions.
+fn scan(src: &str) -> (Vec<SourceElement>, Vec<(String, usize)>) {
+    let mut ret: Vec<SourceElement> = vec![];
+    let mut stray = vec![];
+    let mut open: Vec<usize> = vec![];
+    let mut i = 0;
+    while let Some(j) = src[i..].find('<') {
+        let start = i + j;
+        let rest = &src[start..];
+        i = start + 1;
+
+        if rest.starts_with("<!--") {
+            i = rest.find("-->").map_or(src.len(), |k| start + k + 3);
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            i = rest.find('>').map_or(src.len(), |k| start + k + 1);
+            continue;
+        }
+
+        let end_tag = rest.starts_with("</");
+        let name_start = if end_tag { 2 } else { 1 };
+        let name = rest[name_start..]
+            .split(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        if name.is_empty() || !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            continue;
+        }
+
+        // End of the tag, skipping quoted attribute values.
+        let mut quote = None;
+        let mut len = rest.len();
+        for (k, c) in rest.char_indices().skip(1) {
+            match (quote, c) {
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(q), _) if c == q => quote = None,
+                (None, '>') => {
+                    len = k + 1;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        i = start + len;
+
+        if end_tag {
+            match open.iter().rposition(|&r| ret[r].name == name) {
+                Some(k) => open.truncate(k),
+                // `</p>` and `</br>` are made into elements by the parser.
+                None if name != "p" && name != "br" => stray.push((name, start)),
+                None => {}
+            }
+            continue;
+        }
+
+        while open.last().is_some_and(|&r| closes(&ret[r].name, &name)) {
+            open.pop();
+        }
+        ret.push(SourceElement {
+            name: name.clone(),
+            parent: open.last().copied(),
+            pos: start,
+        });
+        let self_closing = rest[..len].ends_with("/>");
+        if !is_void_element(&name) && !self_closing {
+            open.push(ret.len() - 1);
+        }
+
+        // Contents of raw text elements are not tags.
+        if ["script", "style", "textarea", "title"].contains(&name.as_str()) {
+            let close = format!("</{}", name);
+            if let Some(k) = src[i..].to_ascii_lowercase().find(&close) {
+                i += k;
+            }
+        }
+    }
+    (ret, stray)
+}
+
+fn location(src: &str, pos: usize) -> (usize, usize) {
+    let before = &src[..pos];
+    let line = before.matches('\n').count() + 1;
+    let column = before[before.rfind('\n').map_or(0, |r| r + 1)..]
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
+// Element of the built pattern, in preorder.
+struct BuiltElement<'a> {
+    name: &'a str,
+    parent: Option<usize>,
+}
+
+fn built_elements<'a>(
+    node: &'a PatternNode,
+    parent: Option<usize>,
+    ret: &mut Vec<BuiltElement<'a>>,
+) {
+    let parent = match node.as_element() {
+        Some(e) => {
+            ret.push(BuiltElement {
+                name: &e.name.local,
+                parent,
+            });
+            Some(ret.len() - 1)
+        }
+        None => parent,
+    };
+    for child in node.children.iter() {
+        built_elements(child, parent, ret);
+    }
+}
+
+// Elements of a parsed tree, in preorder.
+fn parsed_elements(node: &NodeRef, parent: Option<usize>, ret: &mut Vec<(NodeRef, Option<usize>)>) {
+    let parent = match node.as_element() {
+        Some(_) => {
+            ret.push((node.clone(), parent));
+            Some(ret.len() - 1)
+        }
+        None => parent,
+    };
+    for child in node.children() {
+        parsed_elements(&child, parent, ret);
+    }
+}
+
+// Names are compared ignoring case, since SVG names such as `clipPath` are adjusted
+// by the parser from lowercase.
+fn same_name(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+struct Pairing<'a, 'b> {
+    source: &'a [SourceElement],
+    built: &'a [BuiltElement<'b>],
+    // Built element of each source element, and whether built elements are paired
+    pairs: Vec<Option<usize>>,
+    used: Vec<bool>,
+}
+
+impl<'a, 'b> Pairing<'a, 'b> {
+    fn pair(&mut self, s: usize, b: usize) {
+        self.pairs[s] = Some(b);
+        self.used[b] = true;
+    }
+
+    // Pairs children of `s` and `b` in order. A source element is also paired with
+    // a descendant of a built sibling through elements inserted by the parser, e.g. `<tr>`.
+    fn walk(&mut self, s: Option<usize>, b: Option<usize>) {
+        let source_cs = (0..self.source.len())
+            .filter(|&i| self.source[i].parent == s)
+            .collect::<Vec<_>>();
+        let built_cs = (0..self.built.len())
+            .filter(|&i| self.built[i].parent == b)
+            .collect::<Vec<_>>();
+        let mut k = 0;
+        for c in source_cs {
+            let found = (k..built_cs.len()).find_map(|j| Some((j, self.find(c, built_cs[j])?)));
+            if let Some((j, found)) = found {
+                self.pair(c, found);
+                // Next elements may be in the same inserted element, e.g. `<tr>`s in `<tbody>`.
+                k = if found == built_cs[j] { j + 1 } else { j };
+                self.walk(Some(c), Some(found));
+            }
+        }
+    }
+
+    // Elements are paired by positions in the trees first, and the others, which are
+    // moved by the parser, are paired by names in order.
+    fn new(source: &'a [SourceElement], built: &'a [BuiltElement<'b>]) -> Self {
+        let mut ret = Pairing {
+            source,
+            built,
+            pairs: vec![None; source.len()],
+            used: vec![false; built.len()],
+        };
+        ret.walk(None, None);
+        for (i, e) in source.iter().enumerate() {
+            if ret.pairs[i].is_none() {
+                let found = (0..built.len()).find(|&k| {
+                    !ret.used[k] && same_name(built[k].name, &e.name) && ret.contains(i, k)
+                });
+                if let Some(k) = found {
+                    ret.pair(i, k);
+                }
+            }
+        }
+        ret
+    }
+
+    // Whether elements already paired in `b` are in `s`. An inserted `<tbody>` around
+    // rows is not paired with a `<tbody>` dropped elsewhere.
+    fn contains(&self, s: usize, b: usize) -> bool {
+        (0..self.source.len()).all(|i| match self.pairs[i] {
+            Some(r)
+                if std::iter::successors(Some(r), |&p| self.built[p].parent).any(|p| p == b) =>
+            {
+                std::iter::successors(Some(i), |&p| self.source[p].parent).any(|p| p == s)
+            }
+            _ => true,
+        })
+    }
+
+    // `b`, or its descendant through unpaired elements, which can be paired with `s`.
+    fn find(&self, s: usize, b: usize) -> Option<usize> {
+        if self.used[b] {
+            return None;
+        }
+        if same_name(self.built[b].name, &self.source[s].name) && self.contains(s, b) {
+            return Some(b);
+        }
+        (b + 1..self.built.len())
+            .filter(|&i| self.built[i].parent == Some(b))
+            .find_map(|i| self.find(s, i))
+    }
+}
+
+// Compares the source with the pattern built from it.
+// `implied` are names of elements which the parser inserts without warnings.
+pub(crate) fn diagnose(src: &str, built: &PatternNode, implied: &[&str]) -> Vec<Warning> {
+    let (source, stray) = scan(src);
+    let mut elements = vec![];
+    built_elements(built, None, &mut elements);
+
+    let Pairing { pairs, used, .. } = Pairing::new(&source, &elements);
+
+    let mut source_of = vec![None; elements.len()];
+    for (i, k) in pairs.iter().enumerate() {
+        if let Some(k) = k {
+            source_of[*k] = Some(i);
+        }
+    }
+    let built_ancestors =
+        |k: usize| std::iter::successors(elements[k].parent, |&r| elements[r].parent);
+    let source_ancestors =
+        |i: usize| std::iter::successors(source[i].parent, |&r| source[r].parent);
+
+    let mut ret = vec![];
+    let mut warn = |kind, pos: usize, message: String| {
+        let (line, column) = location(src, pos);
+        ret.push(Warning {
+            kind,
+            line,
+            column,
+            message,
+        });
+    };
+    let describe = |i: usize| {
+        let (line, column) = location(src, source[i].pos);
+        format!("<{}> at {}:{}", source[i].name, line, column)
+    };
+
+    for (i, e) in source.iter().enumerate() {
+        let k = match pairs[i] {
+            Some(k) => k,
+            None => {
+                warn(
+                    WarningKind::Dropped,
+                    e.pos,
+                    format!("<{}> is ignored by the parser", e.name),
+                );
+                continue;
+            }
+        };
+
+        // The nearest ancestors from the source, skipping inserted and ignored elements.
+        let actual = built_ancestors(k).find_map(|r| source_of[r]);
+        let expected = source_ancestors(i).find(|&r| pairs[r].is_some());
+        if actual == expected {
+            continue;
+        }
+
+        let parent = match expected {
+            Some(p) => p,
+            None => {
+                warn(
+                    WarningKind::Reordered,
+                    e.pos,
+                    format!("<{}> is moved into {}", e.name, describe(actual.unwrap())),
+                );
+                continue;
+            }
+        };
+        let chain = || std::iter::once(parent).chain(source_ancestors(parent));
+        let in_table = chain()
+            .take_while(|&p| Some(p) != actual)
+            .any(|p| source[p].name == "table");
+        // Moved to an ancestor, or to the top when all ancestors are closed.
+        let closed = actual.is_none() || chain().any(|p| Some(p) == actual);
+
+        if in_table {
+            warn(
+                WarningKind::FosterParented,
+                e.pos,
+                format!("<{}> is moved out of {}", e.name, describe(parent)),
+            );
+        } else if closed {
+            warn(
+                WarningKind::AutoClosed,
+                e.pos,
+                format!("{} is closed before <{}>", describe(parent), e.name),
+            );
+        } else {
+            warn(
+                WarningKind::Reordered,
+                e.pos,
+                format!("<{}> is moved out of {}", e.name, describe(parent)),
+            );
+        }
+    }
+
+    for (name, pos) in stray {
+        warn(
+            WarningKind::Dropped,
+            pos,
+            format!("</{}> is ignored by the parser", name),
+        );
+    }
+
+    for (k, e) in elements.iter().enumerate() {
+        if used[k] || implied.iter().any(|r| same_name(r, e.name)) {
+            continue;
+        }
+        // Located at the first element from the source in it, or the one before it.
+        let inside = (k + 1..elements.len())
+            .filter(|&r| built_ancestors(r).any(|a| a == k))
+            .find_map(|r| source_of[r]);
+        let before = (0..k).rev().find_map(|r| source_of[r]);
+        let pos = inside.or(before).map_or(0, |i| source[i].pos);
+        let message = match inside {
+            Some(i) => format!("<{}> is inserted around {}", e.name, describe(i)),
+            None => format!("<{}> is inserted", e.name),
+        };
+        warn(WarningKind::AutoInserted, pos, message);
+    }
+
+    ret.sort_by_key(|r| (r.line, r.column));
+    ret
+}
+
+// Elements of the tree parsed from `src` which are not in the source.
+pub(crate) fn inserted_elements(src: &str, parsed: &NodeRef) -> Vec<NodeRef> {
+    let (source, _) = scan(src);
+    let mut nodes = vec![];
+    parsed_elements(parsed, None, &mut nodes);
+    let elements = nodes
+        .iter()
+        .map(|(node, parent)| BuiltElement {
+            name: &node.as_element().unwrap().name.local,
+            parent: *parent,
+        })
+        .collect::<Vec<_>>();
+
+    let used = Pairing::new(&source, &elements).used;
+    nodes
+        .iter()
+        .zip(used)
+        .filter(|(_, used)| !used)
+        .map(|((node, _), _)| node.clone())
+        .collect()
+}
+
+#[test]
+fn test_scan() {
+    let src = "<ul>\n  <li>a<li title='<b>'>b</ul>\n<p><br><img src=x /></p><script>a<b</script>";
+    let (es, stray) = scan(src);
+    let names = es.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["ul", "li", "li", "p", "br", "img", "script"]);
+    assert_eq!(es[2].parent, Some(0));
+    assert_eq!(es[4].parent, Some(3));
+    assert_eq!(es[6].parent, None);
+    assert_eq!(location(src, es[2].pos), (2, 8));
+    assert!(stray.is_empty());
+
+    let (_, stray) = scan("<div>a</div></div><p></p></p>");
+    assert_eq!(stray, [("div".to_string(), 12)]);
+}
+
+#[test]
+fn test_inserted_elements() {
+    use kuchiki::traits::TendrilSink;
+
+    let name = |node: &NodeRef| node.as_element().unwrap().name.local.to_string();
+    let names = |src: &str| {
+        let parsed = kuchiki::parse_html().one(src);
+        inserted_elements(src, &parsed)
+            .iter()
+            .map(name)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        names("<table><tr><td>a</table><table><thead><tr><td>b<tbody><tr><td>c</table>"),
+        ["html", "head", "body", "tbody"]
+    );
+    // The first table is dropped in `<select>`.
+    assert_eq!(
+        names("<select><table><tbody></tbody></table></select><table><tr><td>a</td></tr></table>"),
+        ["html", "head", "body", "tbody"]
+    );
+}
@@ -0,0 +1,133 @@
+// Encoding detection of documents given as bytes.
+// BOM is checked first, then the charset of the content type,
+// and then `<meta charset>` or XML declaration at the head of the document.
+
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+
+// Number of bytes searched for `<meta>` and XML declaration.
+pub(crate) const PRESCAN_LEN: usize = 1024;
+
+pub(crate) fn sniff(bytes: &[u8], content_type: Option<&str>) -> &'static Encoding {
+    declared(bytes, content_type).unwrap_or_else(|| {
+        if std::str::from_utf8(bytes).is_ok() {
+            UTF_8
+        } else {
+            WINDOWS_1252
+        }
+    })
+}
+
+// Encoding declared by BOM, content type or at the head of the document.
+pub(crate) fn declared(bytes: &[u8], content_type: Option<&str>) -> Option<&'static Encoding> {
+    if let Some((enc, _)) = Encoding::for_bom(bytes) {
+        return Some(enc);
+    }
+
+    if let Some(enc) = content_type.and_then(charset).and_then(for_label) {
+        return Some(enc);
+    }
+
+    let head = &bytes[..bytes.len().min(PRESCAN_LEN)];
+    let enc = prescan(head)?;
+    // Documents which declare UTF-16 in ASCII text are not UTF-16.
+    Some(if is_utf16(enc) { UTF_8 } else { enc })
+}
+
+// Decodes `bytes` and returns the text with the encoding actually used.
+pub(crate) fn decode(bytes: &[u8], content_type: Option<&str>) -> (String, &'static Encoding) {
+    let (text, enc, _) = sniff(bytes, content_type).decode(bytes);
+    (text.into_owned(), enc)
+}
+
+fn is_utf16(enc: &'static Encoding) -> bool {
+    enc == encoding_rs::UTF_16LE || enc == encoding_rs::UTF_16BE
+}
+
+fn for_label(label: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+// Value of `charset` parameter, e.g. `text/html; charset="Shift_JIS"`.
+fn charset(s: &str) -> Option<&str> {
+    let lower = s.to_ascii_lowercase();
+    let i = lower.find("charset")? + "charset".len();
+    let rest = s[i..].trim_start().strip_prefix('=')?.trim_start();
+    let rest = rest.trim_start_matches(['"', '\'']);
+    let len = rest
+        .find(|c: char| c == '"' || c == '\'' || c == ';' || c == '>' || c.is_whitespace())
+        .unwrap_or(rest.len());
+    if len == 0 {
+        None
+    } else {
+        Some(&rest[..len])
+    }
+}
+
+// Finds charset in `<?xml encoding="...">` or `<meta>` tags.
+fn prescan(head: &[u8]) -> Option<&'static Encoding> {
+    // Non-ASCII bytes, e.g. in titles before `<meta>`, can not be a part of tags to find.
+    let head = head
+        .iter()
+        .map(|&b| if b.is_ascii() { b as char } else { '\0' })
+        .collect::<String>();
+    let head = head.as_str();
+    let lower = head.to_ascii_lowercase();
+
+    if lower.starts_with("<?xml") {
+        let end = lower.find("?>")?;
+        let decl = &head[..end];
+        let i = lower[..end].find("encoding")? + "encoding".len();
+        let rest = decl[i..].trim_start().strip_prefix('=')?.trim_start();
+        let quote = rest.chars().next()?;
+        let rest = &rest[1..];
+        return rest.find(quote).and_then(|j| for_label(&rest[..j]));
+    }
+
+    let mut pos = 0;
+    while let Some(i) = lower[pos..].find("<meta") {
+        let start = pos + i;
+        let end = lower[start..].find('>').map_or(lower.len(), |j| start + j);
+        if let Some(enc) = charset(&head[start..end]).and_then(for_label) {
+            return Some(enc);
+        }
+        pos = end;
+    }
+    None
+}
+
+#[test]
+fn test_sniff() {
+    let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode("<p>日本語</p>");
+
+    assert_eq!(sniff(b"\xEF\xBB\xBF<p>a</p>", None), UTF_8);
+    assert_eq!(
+        sniff(&sjis, Some("text/html; charset=Shift_JIS")),
+        encoding_rs::SHIFT_JIS
+    );
+    assert_eq!(
+        sniff(b"<meta charset=\"euc-jp\"><p>a</p>", None),
+        encoding_rs::EUC_JP
+    );
+    assert_eq!(
+        sniff(
+            b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=x-sjis\">",
+            None
+        ),
+        encoding_rs::SHIFT_JIS
+    );
+    assert_eq!(
+        sniff(b"<?xml version=\"1.0\" encoding='EUC-JP'?><a/>", None),
+        encoding_rs::EUC_JP
+    );
+    assert_eq!(sniff(b"<meta charset=\"utf-16\">", None), UTF_8);
+    assert_eq!(sniff(&sjis, None), WINDOWS_1252);
+    assert_eq!(sniff("<p>日本語</p>".as_bytes(), None), UTF_8);
+
+    // non-ASCII texts before `<meta>`
+    let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode(
+        "<title>日本語</title><meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\">",
+    );
+    assert_eq!(sniff(&sjis, None), encoding_rs::SHIFT_JIS);
+    let (eucjp, _, _) = encoding_rs::EUC_JP.encode("<!-- 日本語 --><meta charset=EUC-JP>");
+    assert_eq!(sniff(&eucjp, None), encoding_rs::EUC_JP);
+}
@@ -0,0 +1,186 @@
+// Filters applied to captured values: `{{price|trim|number}}`
+// Each filter takes a value and returns the new one, or `None` if the value is rejected.
+
+use html5ever::data::NAMED_ENTITIES;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use unicode_normalization::UnicodeNormalization;
+
+pub(crate) type Filter = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+#[derive(Clone)]
+pub(crate) struct Filters {
+    map: HashMap<String, Filter>,
+}
+
+// Built-in filters are shared, so that patterns with them can be combined.
+impl Default for Filters {
+    fn default() -> Filters {
+        static BUILTIN: OnceLock<Filters> = OnceLock::new();
+        BUILTIN
+            .get_or_init(|| {
+                let mut ret = Filters {
+                    map: HashMap::new(),
+                };
+                ret.insert("trim", |s| Some(s.trim().to_owned()));
+                ret.insert("collapse", |s| {
+                    Some(s.split_whitespace().collect::<Vec<_>>().join(" "))
+                });
+                ret.insert("lower", |s| Some(s.to_lowercase()));
+                ret.insert("upper", |s| Some(s.to_uppercase()));
+                ret.insert("unescape", |s| Some(unescape(s)));
+                ret.insert("number", number);
+                ret
+            })
+            .clone()
+    }
+}
+
+impl Filters {
+    pub(crate) fn insert<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.map.insert(name.to_owned(), Arc::new(f));
+    }
+
+    // Adds filters of `other`. Filters of the same name must be the same ones.
+    pub(crate) fn extend(&mut self, other: &Filters) -> Result<(), String> {
+        for (name, f) in other.map.iter() {
+            match self.map.get(name) {
+                Some(g) if !Arc::ptr_eq(f, g) => {
+                    return Err(format!("Different filters named \"{}\"", name));
+                }
+                Some(_) => {}
+                None => {
+                    self.map.insert(name.clone(), f.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.map.contains_key(name)
+    }
+
+    // Applies filters in order. Unknown filters are rejected when patterns are built.
+    pub(crate) fn apply(&self, names: &[String], value: String) -> Option<String> {
+        let mut ret = value;
+        for name in names {
+            ret = self.map.get(name)?(&ret)?;
+        }
+        Some(ret)
+    }
+}
+
+// Decodes character references, e.g. `&amp;`, `&#39;` and `&#x27;`.
+fn unescape(s: &str) -> String {
+    let mut ret = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        ret += &rest[..i];
+        rest = &rest[i..];
+
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let name = &rest[1..end];
+        let decoded = if let Some(num) = name.strip_prefix('#') {
+            let code = match num.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => num.parse().ok(),
+            };
+            code.and_then(char::from_u32).map(String::from)
+        } else {
+            NAMED_ENTITIES
+                .get(&rest[1..=end])
+                .filter(|&&(c, _)| c != 0)
+                .map(|&(c1, c2)| {
+                    [c1, c2]
+                        .iter()
+                        .filter(|&&c| c != 0)
+                        .filter_map(|&c| char::from_u32(c))
+                        .collect()
+                })
+        };
+
+        match decoded {
+            Some(decoded) => {
+                ret += &decoded;
+                rest = &rest[end + 1..];
+            }
+            None => {
+                ret.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    ret + rest
+}
+
+// Extracts a number, dropping currency symbols, units and thousands separators.
+// e.g. `"¥1,234円"` to `"1234"`, and `"-$12.50"` to `"-12.50"`.
+// Full-width digits are normalized.
+fn number(s: &str) -> Option<String> {
+    let s = &s.nfkc().collect::<String>();
+    let start = s.find(|c: char| c.is_ascii_digit())?;
+    // Minus sign can be before or after currency symbols, but not in words like `No-1`.
+    let negative = s[..start]
+        .trim_end_matches(|c: char| !c.is_alphanumeric() && c != '-' && c != '−')
+        .strip_suffix(['-', '−'])
+        .is_some_and(|r| !r.ends_with(char::is_alphanumeric));
+
+    let mut ret = String::new();
+    if negative {
+        ret.push('-');
+    }
+    let mut chars = s[start..].chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '0'..='9' => ret.push(c),
+            // Separators are followed by digits.
+            ',' | '.' if chars.peek().is_some_and(|c| c.is_ascii_digit()) => {
+                if c == '.' {
+                    if ret.contains('.') {
+                        break;
+                    }
+                    ret.push('.');
+                }
+            }
+            _ => break,
+        }
+    }
+    Some(ret)
+}
+
+#[test]
+fn test_filters() {
+    let filters = Filters::default();
+    let apply = |names: &[&str], s: &str| {
+        let names = names.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        filters.apply(&names, s.to_owned())
+    };
+
+    assert_eq!(apply(&["trim"], "  a b  ").unwrap(), "a b");
+    assert_eq!(apply(&["collapse"], " a \n  b ").unwrap(), "a b");
+    assert_eq!(apply(&["trim", "lower"], " ABC ").unwrap(), "abc");
+    assert_eq!(
+        apply(
+            &["unescape"],
+            "a &amp;amp; &lt;b&gt; &#39;c&#x27; &unknown; & d"
+        )
+        .unwrap(),
+        "a &amp; <b> 'c' &unknown; & d"
+    );
+
+    assert_eq!(apply(&["number"], "¥1,234円").unwrap(), "1234");
+    assert_eq!(apply(&["number"], "-$12.50").unwrap(), "-12.50");
+    assert_eq!(apply(&["number"], "Price: 3.5 kg.").unwrap(), "3.5");
+    assert_eq!(apply(&["number"], "1.2.3").unwrap(), "1.2");
+    assert_eq!(apply(&["number"], "No-7").unwrap(), "7");
+    assert_eq!(apply(&["number"], "－１，２００円").unwrap(), "-1200");
+    assert_eq!(apply(&["number"], "free"), None);
+    assert_eq!(apply(&["unknown"], "a"), None);
+}
@@ -0,0 +1,131 @@
+// Folding of texts for case-insensitive and Unicode-normalized matching.
+// Texts are compared after folding, and captured values are taken from the original texts
+// by mapping positions of the folded texts back.
+
+use std::fmt;
+use unicode_normalization::char::{canonical_combining_class, is_combining_mark};
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Fold {
+    pub(crate) case: bool,
+    pub(crate) nfkc: bool,
+    pub(crate) diacritics: bool,
+}
+
+impl Fold {
+    // Parses the value of the special attribute, e.g. `fold="case nfkc"`.
+    pub(crate) fn parse(s: &str) -> Result<Fold, String> {
+        let mut ret = Fold::default();
+        for w in s.split_whitespace() {
+            match w {
+                "case" => ret.case = true,
+                "nfkc" => ret.nfkc = true,
+                "diacritics" => ret.diacritics = true,
+                _ => return Err(format!("Unknown fold: \"{}\"", w)),
+            }
+        }
+        Ok(ret)
+    }
+
+    pub(crate) fn union(self, other: Fold) -> Fold {
+        Fold {
+            case: self.case || other.case,
+            nfkc: self.nfkc || other.nfkc,
+            diacritics: self.diacritics || other.diacritics,
+        }
+    }
+
+    pub(crate) fn is_empty(self) -> bool {
+        self == Fold::default()
+    }
+
+    pub(crate) fn apply(self, s: &str) -> String {
+        let mut ret = if self.diacritics {
+            let s = s.nfd().filter(|&c| !is_combining_mark(c));
+            if self.nfkc {
+                s.nfkc().collect::<String>()
+            } else {
+                s.nfc().collect()
+            }
+        } else if self.nfkc {
+            s.nfkc().collect()
+        } else {
+            s.to_owned()
+        };
+        if self.case {
+            ret = ret.to_lowercase();
+        }
+        ret
+    }
+
+    // Folded text, and positions in `s` for each byte position of the folded text.
+    // Positions inside a folded character are mapped to the start of the original one.
+    pub(crate) fn apply_with_offsets(self, s: &str) -> (String, Vec<usize>) {
+        let mut ret = String::new();
+        let mut offsets = vec![];
+        let mut push = |seg: &str, start: usize| {
+            let folded = self.apply(seg);
+            offsets.extend(std::iter::repeat_n(start, folded.len()));
+            ret += &folded;
+        };
+
+        // Segments are folded separately, as long as it gives the same result
+        // as folding them together, e.g. not for `ｶﾞ`.
+        let mut start = 0;
+        for (i, c) in s.char_indices().skip(1) {
+            let next = &s[i..i + c.len_utf8()];
+            if canonical_combining_class(c) == 0
+                && self.apply(&s[start..i + next.len()])
+                    == self.apply(&s[start..i]) + &self.apply(next)
+            {
+                push(&s[start..i], start);
+                start = i;
+            }
+        }
+        push(&s[start..], start);
+
+        offsets.push(s.len());
+        (ret, offsets)
+    }
+}
+
+// Words of the special attribute.
+impl fmt::Display for Fold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words = [
+            (self.case, "case"),
+            (self.nfkc, "nfkc"),
+            (self.diacritics, "diacritics"),
+        ];
+        let words = words
+            .iter()
+            .filter(|r| r.0)
+            .map(|r| r.1)
+            .collect::<Vec<_>>();
+        f.write_str(&words.join(" "))
+    }
+}
+
+#[test]
+fn test_fold() {
+    let all = Fold::parse("case nfkc diacritics").unwrap();
+    assert!(Fold::parse("case foo").is_err());
+    assert_eq!(all.to_string(), "case nfkc diacritics");
+
+    assert_eq!(all.apply("Ｃａｆé １２３"), "cafe 123");
+    assert_eq!(Fold::parse("nfkc").unwrap().apply("ｶﾞｲﾄﾞ"), "ガイド");
+    assert_eq!(
+        Fold::parse("diacritics")
+            .unwrap()
+            .apply("Cre\u{300}me Brûlée"),
+        "Creme Brulee"
+    );
+
+    let (folded, offsets) = all.apply_with_offsets("ＡBｶﾞé!");
+    assert_eq!(folded, "abガe!");
+    let orig = |i: usize, j: usize| &"ＡBｶﾞé!"[offsets[i]..offsets[j]];
+    assert_eq!(orig(0, 1), "Ａ");
+    assert_eq!(orig(2, 2 + "ガ".len()), "ｶﾞ");
+    assert_eq!(orig(folded.len() - 2, folded.len()), "é!");
+}
@@ -0,0 +1,72 @@
+// Fuzzy matching of literal texts in patterns.
+// A literal matches when the edit distance is small enough, or when most of the words
+// are the same. The score of the match is the similarity by edit distance.
+
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Fuzzy {
+    pub(crate) max_distance: usize,
+    pub(crate) min_similarity: f64,
+}
+
+impl Fuzzy {
+    // Score of `doc` for literal `pat`, or `None` if they are too different.
+    // The score is less than 1 unless they are the same.
+    pub(crate) fn score(&self, doc: &str, pat: &str) -> Option<f64> {
+        let d = levenshtein(doc, pat);
+        if d > self.max_distance && token_similarity(doc, pat) < self.min_similarity {
+            return None;
+        }
+        let len = doc.chars().count().max(pat.chars().count()).max(1);
+        Some(1.0 - d as f64 / len as f64)
+    }
+}
+
+// Edit distance by characters.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+// Jaccard index of sets of lowercased words.
+fn token_similarity(a: &str, b: &str) -> f64 {
+    let tokens = |s: &str| {
+        s.split(|c: char| !c.is_alphanumeric())
+            .filter(|r| !r.is_empty())
+            .map(|r| r.to_lowercase())
+            .collect::<HashSet<_>>()
+    };
+    let (a, b) = (tokens(a), tokens(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+#[test]
+fn test_fuzzy() {
+    assert_eq!(levenshtein("kitten", "sitting"), 3);
+    assert_eq!(levenshtein("", "日本"), 2);
+    assert_eq!(token_similarity("Release date", "Release Date:"), 1.0);
+    assert_eq!(token_similarity("Release date", "Release"), 0.5);
+
+    let fuzzy = Fuzzy {
+        max_distance: 1,
+        min_similarity: 0.8,
+    };
+    assert_eq!(fuzzy.score("Price", "Price"), Some(1.0));
+    assert_eq!(fuzzy.score("Prices", "Price"), Some(1.0 - 1.0 / 6.0));
+    assert!(fuzzy.score("Release Date:", "Release date").unwrap() < 1.0);
+    assert_eq!(fuzzy.score("Released", "Release date"), None);
+}
@@ -0,0 +1,199 @@
+// Index of elements by tag name, id and class words.
+// Nodes are identified by preorder positions, so that whether a subtree contains
+// candidates of a pattern node can be checked by binary search.
+
+use crate::pattern::PatternNode;
+use crate::{has_placeholder, node_id};
+use html5ever::LocalName;
+use kuchiki::iter::NodeEdge;
+use kuchiki::{ExpandedName, Node, NodeRef};
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+
+// Map from addresses of nodes, which hashes faster than the default hasher.
+pub(crate) type AddrMap<K, V> = HashMap<*const K, V, BuildHasherDefault<AddrHasher>>;
+
+#[derive(Default)]
+pub(crate) struct AddrHasher(u64);
+
+impl Hasher for AddrHasher {
+    fn finish(&self) -> u64 {
+        // finalizer of MurmurHash3
+        let mut h = self.0;
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        h ^ (h >> 33)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = self.0.rotate_left(8) ^ b as u64;
+        }
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.0 ^= n as u64;
+    }
+}
+
+pub(crate) struct Index {
+    // Preorder position of each node, and the end of its subtree
+    pos: AddrMap<Node, (usize, usize)>,
+    documents: Vec<usize>,
+    tags: HashMap<LocalName, Vec<usize>>,
+    ids: HashMap<String, Vec<usize>>,
+    classes: HashMap<String, Vec<usize>>,
+}
+
+impl Index {
+    pub(crate) fn new(root: &NodeRef) -> Index {
+        let mut ret = Index {
+            pos: AddrMap::default(),
+            documents: vec![],
+            tags: HashMap::new(),
+            ids: HashMap::new(),
+            classes: HashMap::new(),
+        };
+
+        let mut next = 0;
+        for edge in root.traverse_inclusive() {
+            match edge {
+                NodeEdge::Start(node) => {
+                    ret.pos.insert(node_id(&node), (next, next));
+                    if node.as_document().is_some() {
+                        ret.documents.push(next);
+                    }
+                    if let Some(e) = node.as_element() {
+                        let tag = e.name.local.clone();
+                        ret.tags.entry(tag).or_default().push(next);
+
+                        let attrs = e.attributes.borrow();
+                        for (name, map) in [("id", &mut ret.ids), ("class", &mut ret.classes)] {
+                            if let Some(value) = attrs.map.get(&ExpandedName::new("", name)) {
+                                for w in value.value.split_whitespace() {
+                                    map.entry(w.to_owned()).or_default().push(next);
+                                }
+                            }
+                        }
+                    }
+                    next += 1;
+                }
+                NodeEdge::End(node) => {
+                    if let Some(p) = ret.pos.get_mut(&node_id(&node)) {
+                        p.1 = next;
+                    }
+                }
+            }
+        }
+
+        ret
+    }
+
+    // Sorted positions of nodes which can match to `pattern`,
+    // or `None` if any node can.
+    pub(crate) fn candidates(&self, pattern: &PatternNode) -> Option<&[usize]> {
+        if pattern.is_document() {
+            return Some(&self.documents);
+        }
+
+        let e = pattern.as_element()?;
+        let mut ret = self.tags.get(&e.name.local).map_or(&[][..], |r| &r[..]);
+        // Ids and class words are not folded in the index.
+        if !pattern.fold.is_empty() {
+            return Some(ret);
+        }
+
+        let attrs = &e.attributes;
+        for (name, map) in [("id", &self.ids), ("class", &self.classes)] {
+            let value = match attrs.map.get(&ExpandedName::new("", name)) {
+                Some(value) if !has_placeholder(&value.value) => value,
+                _ => continue,
+            };
+            for w in value.value.split_whitespace() {
+                let cs = map.get(w).map_or(&[][..], |r| &r[..]);
+                if cs.len() < ret.len() {
+                    ret = cs;
+                }
+            }
+        }
+
+        Some(ret)
+    }
+
+    // Whether `doc` is one of `cands`.
+    pub(crate) fn is_candidate(&self, doc: &NodeRef, cands: &[usize]) -> bool {
+        match self.pos.get(&node_id(doc)) {
+            Some((start, _)) => cands.binary_search(start).is_ok(),
+            None => true,
+        }
+    }
+
+    // Whether descendants of `doc` contain any of `cands`.
+    // `doc` itself is included if `inclusive`.
+    pub(crate) fn contains(&self, doc: &NodeRef, cands: &[usize], inclusive: bool) -> bool {
+        let (start, end) = match self.pos.get(&node_id(doc)) {
+            Some(&(start, end)) if inclusive => (start, end),
+            Some(&(start, end)) => (start + 1, end),
+            None => return true,
+        };
+        let i = cands.partition_point(|&r| r < start);
+        i < cands.len() && cands[i] < end
+    }
+}
+
+#[test]
+fn test_index() {
+    use crate::fold::Fold;
+    use kuchiki::traits::*;
+
+    let doc = kuchiki::parse_html()
+        .one(r#"<div id="a"><p class="x y">1</p></div><div><p class="y">2</p><p>3</p></div>"#);
+    let index = Index::new(&doc);
+    let pat = |s: &str| {
+        let doc = kuchiki::parse_html().one(s);
+        PatternNode::new(
+            doc.select_first("body > *").unwrap().as_node(),
+            Fold::default(),
+        )
+        .unwrap()
+    };
+
+    assert_eq!(index.candidates(&pat("<p></p>")).unwrap().len(), 3);
+    assert_eq!(
+        index
+            .candidates(&pat(r#"<p class="y"></p>"#))
+            .unwrap()
+            .len(),
+        2
+    );
+    assert_eq!(
+        index
+            .candidates(&pat(r#"<p class="x y"></p>"#))
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        index
+            .candidates(&pat(r#"<p class="{{c}}"></p>"#))
+            .unwrap()
+            .len(),
+        3
+    );
+    assert!(index
+        .candidates(&pat(r#"<div id="b"></div>"#))
+        .unwrap()
+        .is_empty());
+
+    let divs = doc
+        .select("div")
+        .unwrap()
+        .map(|r| r.as_node().clone())
+        .collect::<Vec<_>>();
+    let cs = index.candidates(&pat(r#"<p class="x"></p>"#)).unwrap();
+    assert!(index.contains(&divs[0], cs, false));
+    assert!(!index.contains(&divs[1], cs, false));
+    assert!(!index.is_candidate(&divs[0], cs));
+}
@@ -0,0 +1,747 @@
+// Pattern inference from example documents.
+
+use crate::print::{escape_attr, escape_text, is_void_element};
+use crate::{filter_whitespace, is_special_attr, Pattern};
+use html5ever::QualName;
+use kuchiki::{parse_html, traits::*, Attribute, ExpandedName, NodeRef};
+use std::collections::{BTreeMap, BTreeSet};
+
+// Where an example value was found.
+#[derive(Clone)]
+struct Occurrence {
+    // Text node, or element owning the attribute.
+    node: NodeRef,
+    attr: Option<String>,
+    // Byte range of the value in the text or attribute value.
+    start: usize,
+    end: usize,
+}
+
+pub(crate) fn infer(html: &str, examples: &[(&str, &str)]) -> Result<String, String> {
+    if examples.is_empty() {
+        return Err("no examples given".to_owned());
+    }
+
+    let doc = filter_whitespace(parse_html().one(html)).unwrap();
+
+    let mut candidates = vec![];
+    for (name, value) in examples.iter() {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(format!("example value for `{}` is empty", name));
+        }
+        let occs = find_occurrences(&doc, value);
+        if occs.is_empty() {
+            return Err(format!(
+                "example value for `{}` is not found in the document: \"{}\"",
+                name, value
+            ));
+        }
+        candidates.push(occs);
+    }
+
+    let chosen = choose_occurrences(&candidates);
+    let anchors = chosen
+        .iter()
+        .map(|occ| occ.node.clone())
+        .collect::<Vec<_>>();
+    let root = common_ancestor(&anchors).ok_or("examples have no common element")?;
+
+    let holes = chosen
+        .into_iter()
+        .zip(examples.iter().map(|(name, _)| *name))
+        .collect::<Vec<_>>();
+
+    let mut out = String::new();
+    write_source(&prune(&root, &anchors, &holes), 0, &mut out);
+
+    let pat = Pattern::new(&out)?;
+    let expected = examples
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.trim().to_owned()))
+        .collect::<BTreeMap<_, _>>();
+    if !pat.matches(html).contains(&expected) {
+        return Err(format!(
+            "inferred pattern does not reproduce the examples:\n{}",
+            out
+        ));
+    }
+
+    Ok(out)
+}
+
+fn find_occurrences(doc: &NodeRef, value: &str) -> Vec<Occurrence> {
+    let mut exact = vec![];
+    let mut partial = vec![];
+
+    for node in doc.descendants() {
+        if let Some(text) = node.as_text() {
+            let text = text.borrow();
+            if let Some(start) = text.find(value) {
+                let occ = Occurrence {
+                    node: node.clone(),
+                    attr: None,
+                    start,
+                    end: start + value.len(),
+                };
+                if text.as_str() == value {
+                    exact.push(occ);
+                } else {
+                    partial.push(occ);
+                }
+            }
+        } else if let Some(element) = node.as_element() {
+            for (k, v) in element.attributes.borrow().map.iter() {
+                if let Some(start) = v.value.find(value) {
+                    let occ = Occurrence {
+                        node: node.clone(),
+                        attr: Some(k.local.to_string()),
+                        start,
+                        end: start + value.len(),
+                    };
+                    if v.value.trim() == value {
+                        exact.push(occ);
+                    } else {
+                        partial.push(occ);
+                    }
+                }
+            }
+        }
+    }
+
+    if exact.is_empty() {
+        partial
+    } else {
+        exact
+    }
+}
+
+// Picks one occurrence per example so that their common ancestor is as small as possible.
+fn choose_occurrences(candidates: &[Vec<Occurrence>]) -> Vec<Occurrence> {
+    let mut best: Option<(usize, Vec<Occurrence>)> = None;
+
+    for first in candidates[0].iter() {
+        let mut chosen = vec![first.clone()];
+        let mut anchors = vec![first.node.clone()];
+
+        for occs in candidates[1..].iter() {
+            let occ = occs
+                .iter()
+                .min_by_key(|occ| {
+                    let mut anchors = anchors.clone();
+                    anchors.push(occ.node.clone());
+                    common_ancestor(&anchors).map_or(usize::MAX, |r| subtree_size(&r))
+                })
+                .unwrap();
+            anchors.push(occ.node.clone());
+            chosen.push(occ.clone());
+        }
+
+        let size = common_ancestor(&anchors).map_or(usize::MAX, |r| subtree_size(&r));
+        let better = match &best {
+            Some((s, _)) => size < *s,
+            None => true,
+        };
+        if better {
+            best = Some((size, chosen));
+        }
+    }
+
+    best.unwrap().1
+}
+
+fn common_ancestor(nodes: &[NodeRef]) -> Option<NodeRef> {
+    let path = |node: &NodeRef| {
+        let mut ret = node.inclusive_ancestors().collect::<Vec<_>>();
+        ret.reverse();
+        ret
+    };
+
+    let mut common = path(&nodes[0]);
+    for node in nodes[1..].iter() {
+        let p = path(node);
+        let len = common
+            .iter()
+            .zip(p.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        common.truncate(len);
+    }
+
+    common.into_iter().rev().find(|r| r.as_element().is_some())
+}
+
+fn subtree_size(node: &NodeRef) -> usize {
+    node.descendants().count()
+}
+
+fn is_kept(node: &NodeRef, anchors: &[NodeRef]) -> bool {
+    anchors
+        .iter()
+        .any(|a| a.inclusive_ancestors().any(|r| r == *node))
+}
+
+// Copies the part of the subtree containing the anchors, replacing example values with placeholders.
+fn prune(node: &NodeRef, anchors: &[NodeRef], holes: &[(Occurrence, &str)]) -> NodeRef {
+    if let Some(text) = node.as_text() {
+        let ps = holes
+            .iter()
+            .filter(|(occ, _)| occ.node == *node && occ.attr.is_none())
+            .collect::<Vec<_>>();
+        return NodeRef::new_text(fill_holes(&text.borrow(), &ps));
+    }
+
+    let element = node.as_element().unwrap();
+
+    let mut attrs = vec![];
+    for (k, v) in element.attributes.borrow().map.iter() {
+        let ps = holes
+            .iter()
+            .filter(|(occ, _)| occ.node == *node && occ.attr.as_deref() == Some(k.local.as_ref()))
+            .collect::<Vec<_>>();
+
+        let value = if !ps.is_empty() {
+            fill_holes(&v.value, &ps)
+        } else if k.local.as_ref() == "class" {
+            let ws = v
+                .value
+                .split_whitespace()
+                .filter(|w| is_stable_token(w))
+                .collect::<Vec<_>>();
+            if ws.is_empty() {
+                continue;
+            }
+            ws.join(" ")
+        } else if k.local.as_ref() == "id"
+            && is_stable_token(&v.value)
+            // Ids with numbers are usually of each record, e.g. `entry-1`.
+            && !v.value.contains(|c: char| c.is_ascii_digit())
+        {
+            v.value.clone()
+        } else {
+            continue;
+        };
+
+        attrs.push((k.clone(), value));
+    }
+
+    let ret = new_element(&element.name, attrs);
+
+    let children = node.children().collect::<Vec<_>>();
+    let kept = (0..children.len())
+        .filter(|&i| is_kept(&children[i], anchors))
+        .collect::<Vec<_>>();
+
+    for (ix, &i) in kept.iter().enumerate() {
+        if ix > 0 && kept[ix - 1] + 1 != i {
+            ret.append(NodeRef::new_text("..."));
+        }
+        ret.append(prune(&children[i], anchors, holes));
+    }
+
+    ret
+}
+
+fn fill_holes(s: &str, holes: &[&(Occurrence, &str)]) -> String {
+    let mut holes = holes.to_vec();
+    holes.sort_by_key(|(occ, _)| occ.start);
+
+    let mut ret = String::new();
+    let mut cur = 0;
+    for (occ, name) in holes {
+        if occ.start < cur {
+            // Overlapping examples
+            continue;
+        }
+        ret += &s[cur..occ.start];
+        ret += &placeholder(name);
+        cur = occ.end;
+    }
+    ret += &s[cur..];
+    ret
+}
+
+fn placeholder(name: &str) -> String {
+    format!("{{{{{}}}}}", name)
+}
+
+pub(crate) fn generalize(samples: &[NodeRef]) -> Result<String, String> {
+    if samples.is_empty() {
+        return Err("no samples given".to_owned());
+    }
+
+    let samples = samples
+        .iter()
+        .map(|r| filter_whitespace(r.clone()))
+        .collect::<Option<Vec<_>>>()
+        .ok_or("samples must be elements")?;
+    if samples.iter().any(|r| r.as_element().is_none()) {
+        return Err("samples must be elements".to_owned());
+    }
+
+    let mut g = Generalizer {
+        names: BTreeSet::new(),
+    };
+    let tree = g
+        .merge(&samples, "")
+        .ok_or("samples have different root elements")?;
+
+    let mut out = String::new();
+    write_source(&tree, 0, &mut out);
+
+    // Samples are matched without reparsing, which would drop stray rows, options etc.
+    let pat = Pattern::new(&out)?;
+    for (i, sample) in samples.iter().enumerate() {
+        let doc = NodeRef::new_document();
+        doc.append(sample.clone());
+        if !pat.matches_tree(&doc) {
+            return Err(format!(
+                "generalized pattern does not match sample #{}:\n{}",
+                i, out
+            ));
+        }
+    }
+
+    Ok(out)
+}
+
+struct Generalizer {
+    // Placeholder names already used
+    names: BTreeSet<String>,
+}
+
+impl Generalizer {
+    // Computes the pattern which covers all of `nodes`.
+    fn merge(&mut self, nodes: &[NodeRef], hint: &str) -> Option<NodeRef> {
+        if nodes.iter().all(|r| r.as_text().is_some()) {
+            let texts = nodes
+                .iter()
+                .map(|r| r.as_text().unwrap().borrow().clone())
+                .collect::<Vec<_>>();
+            return Some(NodeRef::new_text(self.merge_values(&texts, hint)));
+        }
+
+        let elements = nodes
+            .iter()
+            .map(|r| r.as_element())
+            .collect::<Option<Vec<_>>>()?;
+        let name = &elements[0].name;
+        if elements.iter().any(|e| e.name != *name) {
+            return None;
+        }
+
+        let mut attrs = vec![];
+        let first = elements[0].attributes.borrow();
+        for (k, v) in first.map.iter() {
+            let values = elements
+                .iter()
+                .map(|e| e.attributes.borrow().map.get(k).map(|r| r.value.clone()))
+                .collect::<Option<Vec<_>>>();
+            let values = match values {
+                Some(values) => values,
+                None => continue,
+            };
+
+            let key = k.local.as_ref();
+            let value = if key == "class" {
+                // Same as the subset rule of matching
+                let ws = v
+                    .value
+                    .split_whitespace()
+                    .filter(|w| values.iter().all(|r| r.split_whitespace().any(|x| x == *w)))
+                    .collect::<Vec<_>>();
+                if ws.is_empty() {
+                    continue;
+                }
+                ws.join(" ")
+            } else if values.iter().all(|r| *r == v.value) {
+                v.value.clone()
+            } else if key == "id" || key == "style" {
+                continue;
+            } else {
+                self.merge_values(&values, key)
+            };
+
+            attrs.push((k.clone(), value));
+        }
+
+        let hint = first
+            .map
+            .iter()
+            .find(|(k, _)| k.local.as_ref() == "class")
+            .and_then(|(_, v)| v.value.split_whitespace().find(|w| is_stable_token(w)))
+            .unwrap_or_else(|| name.local.as_ref())
+            .to_owned();
+        drop(first);
+
+        let children = nodes
+            .iter()
+            .map(|r| r.children().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let columns = align(&children);
+
+        // Interior positions where some sample has extra siblings
+        let gaps = (1..columns.len())
+            .filter(|&i| (0..nodes.len()).any(|t| columns[i - 1].0[t] + 1 != columns[i].0[t]))
+            .collect::<Vec<_>>();
+
+        let subseq = gaps.len() > 1;
+        if subseq {
+            attrs.push((ExpandedName::new("", "subseq"), String::new()));
+        }
+
+        let ret = new_element(name, attrs);
+        for (i, (_, column)) in columns.iter().enumerate() {
+            if !subseq && gaps.contains(&i) {
+                ret.append(NodeRef::new_text("..."));
+            }
+            ret.append(self.merge(column, &hint)?);
+        }
+
+        Some(ret)
+    }
+
+    // Makes a placeholder for differing values, keeping common words around it.
+    fn merge_values(&mut self, values: &[String], hint: &str) -> String {
+        if values.iter().all(|r| *r == values[0]) {
+            return values[0].clone();
+        }
+
+        let prefix = values[1..].iter().fold(values[0].as_str(), |acc, r| {
+            let len = acc
+                .char_indices()
+                .zip(r.chars())
+                .take_while(|((_, a), b)| a == b)
+                .last()
+                .map_or(0, |((i, c), _)| i + c.len_utf8());
+            &acc[..len]
+        });
+        let prefix = &prefix[..prefix
+            .rfind(|c: char| !c.is_alphanumeric())
+            .map_or(0, |i| i + prefix[i..].chars().next().unwrap().len_utf8())];
+
+        let rests = values
+            .iter()
+            .map(|r| &r[prefix.len()..])
+            .collect::<Vec<_>>();
+        let suffix = rests[1..].iter().fold(rests[0], |acc, r| {
+            let len = acc
+                .chars()
+                .rev()
+                .zip(r.chars().rev())
+                .take_while(|(a, b)| a == b)
+                .map(|(c, _)| c.len_utf8())
+                .sum::<usize>();
+            &acc[acc.len() - len..]
+        });
+        let suffix = &suffix[suffix
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or(suffix.len())..];
+
+        let affix = |s: &str| s.chars().any(|c| c.is_alphabetic());
+        let prefix = if affix(prefix) { prefix } else { "" };
+        let suffix = if affix(suffix) && rests.iter().all(|r| r.len() > suffix.len()) {
+            suffix
+        } else {
+            ""
+        };
+
+        format!(
+            "{}{}{}",
+            prefix,
+            placeholder(&self.fresh_name(hint)),
+            suffix
+        )
+    }
+
+    fn fresh_name(&mut self, hint: &str) -> String {
+        let hint = if hint.is_empty() { "value" } else { hint };
+        let mut name = hint.to_owned();
+        let mut i = 2;
+        while self.names.contains(&name) {
+            name = format!("{}{}", hint, i);
+            i += 1;
+        }
+        self.names.insert(name.clone());
+        name
+    }
+}
+
+// Aligns children of all samples by the longest common subsequence.
+// Returns indices of aligned nodes in each sample, and the nodes.
+fn align(children: &[Vec<NodeRef>]) -> Vec<(Vec<usize>, Vec<NodeRef>)> {
+    // Same kind of nodes can be aligned, and ones with the same contents are preferred.
+    let score = |a: &NodeRef, b: &NodeRef| {
+        let key = |r: &NodeRef| r.as_element().map(|e| e.name.local.to_string());
+        if key(a) != key(b) {
+            0
+        } else if a.text_contents() == b.text_contents() {
+            2
+        } else {
+            1
+        }
+    };
+
+    let mut columns = children[0]
+        .iter()
+        .enumerate()
+        .map(|(i, r)| (vec![i], vec![r.clone()]))
+        .collect::<Vec<_>>();
+
+    for cs in children[1..].iter() {
+        let n = columns.len();
+        let m = cs.len();
+        let mut dp = vec![vec![0; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                let w = score(&columns[i].1[0], &cs[j]);
+                dp[i][j] = dp[i + 1][j].max(dp[i][j + 1]);
+                if w > 0 {
+                    dp[i][j] = dp[i][j].max(dp[i + 1][j + 1] + w);
+                }
+            }
+        }
+
+        let mut next = vec![];
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            let w = score(&columns[i].1[0], &cs[j]);
+            if w > 0 && dp[i][j] == dp[i + 1][j + 1] + w {
+                let (mut ixs, mut nodes) = columns[i].clone();
+                ixs.push(j);
+                nodes.push(cs[j].clone());
+                next.push((ixs, nodes));
+                i += 1;
+                j += 1;
+            } else if dp[i + 1][j] >= dp[i][j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        columns = next;
+    }
+
+    columns
+}
+
+fn new_element(name: &QualName, attrs: Vec<(ExpandedName, String)>) -> NodeRef {
+    NodeRef::new_element(
+        name.clone(),
+        attrs.into_iter().map(|(k, value)| {
+            (
+                k,
+                Attribute {
+                    prefix: None,
+                    value,
+                },
+            )
+        }),
+    )
+}
+
+// Prints a pattern tree in the pattern syntax.
+fn write_source(node: &NodeRef, depth: usize, out: &mut String) {
+    let indent = "    ".repeat(depth);
+
+    if let Some(text) = node.as_text() {
+        out.push_str(&indent);
+        out.push_str(&escape_text(&text.borrow()));
+        out.push('\n');
+        return;
+    }
+
+    let element = node.as_element().unwrap();
+    let name = element.name.local.as_ref();
+
+    out.push_str(&indent);
+    out.push('<');
+    out.push_str(name);
+    for (k, v) in element.attributes.borrow().map.iter() {
+        if is_special_attr(k) {
+            out.push_str(&format!(" {}", k.local));
+        } else {
+            out.push_str(&format!(" {}=\"{}\"", k.local, escape_attr(&v.value)));
+        }
+    }
+    out.push('>');
+
+    if is_void_element(name) {
+        out.push('\n');
+        return;
+    }
+
+    let children = node.children().collect::<Vec<_>>();
+
+    // Put a single text child inline: `<a>{{title}}</a>`
+    if children.len() == 1 && children[0].as_text().is_some() {
+        out.push_str(&escape_text(&children[0].as_text().unwrap().borrow()));
+    } else if !children.is_empty() {
+        out.push('\n');
+        for child in children.iter() {
+            write_source(child, depth + 1, out);
+        }
+        out.push_str(&indent);
+    }
+
+    out.push_str(&format!("</{}>\n", name));
+}
+
+// Class words and ids generated by CSS-in-JS or templates change on every build.
+// Words with hash-like parts, e.g. `css-1x2y3z` or `a8f3c2`, are regarded as generated,
+// while ones such as `h2` or `col-md-6` are kept.
+fn is_stable_token(s: &str) -> bool {
+    !s.is_empty()
+        && !s.split(['-', '_']).any(is_hash_like)
+        && !["css-", "sc-", "jsx-", "ng-"]
+            .iter()
+            .any(|p| s.starts_with(p))
+}
+
+// Long hex runs with digits, or letters and digits mixed alternately.
+fn is_hash_like(s: &str) -> bool {
+    let digits = s.chars().filter(|c| c.is_ascii_digit()).count();
+    let switches = s
+        .as_bytes()
+        .windows(2)
+        .filter(|r| r[0].is_ascii_digit() != r[1].is_ascii_digit())
+        .count();
+    s.len() >= 5 && digits > 0 && (s.chars().all(|c| c.is_ascii_hexdigit()) || switches >= 2)
+}
+
+#[test]
+fn test_infer() {
+    let doc = r#"
+<!DOCTYPE html>
+<html lang="en">
+    <body>
+        <div class="entry css-1x2y3z" id="entry-1">
+            <h3 class="title"><a href="/a" data-id="123">Foo</a></h3>
+            <span class="date">2020-01-01</span>
+            <p>Posted by Alice</p>
+        </div>
+        <div class="entry css-4a5b6c" id="entry-2">
+            <h3 class="title"><a href="/b" data-id="456">Bar</a></h3>
+            <span class="date">2020-01-02</span>
+            <p>Posted by Bob</p>
+        </div>
+    </body>
+</html>
+"#;
+
+    let src = Pattern::infer(doc, &[("title", "Foo"), ("url", "/a"), ("user", "Alice")]).unwrap();
+    assert_eq!(
+        src,
+        r#"<div class="entry">
+    <h3 class="title">
+        <a href="{{url}}">{{title}}</a>
+    </h3>
+    ...
+    <p>Posted by {{user}}</p>
+</div>
+"#
+    );
+
+    let ms = Pattern::new(&src).unwrap().matches(doc);
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[1]["title"], "Bar");
+    assert_eq!(ms[1]["url"], "/b");
+    assert_eq!(ms[1]["user"], "Bob");
+
+    assert!(Pattern::infer(doc, &[("title", "Baz")]).is_err());
+
+    assert!(["h2", "col-md-6", "item12", "mt-3"]
+        .iter()
+        .all(|r| is_stable_token(r)));
+    assert!(!["css-1x2y3z", "a8f3c2", "x7Kq9w", "card_1a2b3"]
+        .iter()
+        .any(|r| is_stable_token(r)));
+}
+
+#[test]
+fn test_generalize() {
+    let doc = parse_html().one(
+        r#"
+<ul>
+    <li class="item item-1">
+        <a href="/users/foo/info">Foo</a>
+        <span class="date">2020-01-01</span>
+    </li>
+    <li class="item item-2">
+        <a href="/users/bar/info">Bar</a>
+        <img src="new.png">
+        <span class="date">2020-01-02</span>
+    </li>
+</ul>
+"#,
+    );
+    let samples = doc
+        .select("li")
+        .unwrap()
+        .map(|r| r.as_node().clone())
+        .collect::<Vec<_>>();
+
+    let src = Pattern::generalize(&samples).unwrap();
+    assert_eq!(
+        src,
+        r#"<li class="item">
+    <a href="/users/{{href}}/info">{{a}}</a>
+    ...
+    <span class="date">{{date}}</span>
+</li>
+"#
+    );
+}
+
+#[test]
+fn test_generalize_subseq() {
+    let doc = parse_html().one(
+        r#"
+<div><ul><li>A</li><li>X</li><li>B</li><li>Y</li><li>C</li></ul></div>
+<div><ul><li>A</li><li>B</li><li>C</li></ul></div>
+"#,
+    );
+    let samples = doc
+        .select("ul")
+        .unwrap()
+        .map(|r| r.as_node().clone())
+        .collect::<Vec<_>>();
+
+    let src = Pattern::generalize(&samples).unwrap();
+    assert_eq!(
+        src,
+        r#"<ul subseq>
+    <li>A</li>
+    <li>B</li>
+    <li>C</li>
+</ul>
+"#
+    );
+}
+
+#[test]
+fn test_generalize_rows() {
+    let doc = parse_html().one(
+        r#"
+<table>
+    <tr><th>Foo</th><td class="price">100</td></tr>
+    <tr><th>Bar</th><td class="price">200</td></tr>
+</table>
+"#,
+    );
+    let samples = doc
+        .select("tr")
+        .unwrap()
+        .map(|r| r.as_node().clone())
+        .collect::<Vec<_>>();
+
+    let src = Pattern::generalize(&samples).unwrap();
+    assert_eq!(
+        src,
+        r#"<tr>
+    <th>{{th}}</th>
+    <td class="price">{{price}}</td>
+</tr>
+"#
+    );
+}
@@ -0,0 +1,110 @@
+// JSON placeholders: `{{name:json}}` and `{{name:json:$.path.to[0].value}}`
+
+use serde_json::Value;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Step {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+fn parse_path(path: &str) -> Result<Vec<Step>, String> {
+    let err = || format!("Invalid JSON path: \"{}\"", path);
+
+    let mut cur = path.trim();
+    cur = cur.strip_prefix('$').ok_or_else(err)?;
+
+    let mut ret = vec![];
+    while !cur.is_empty() {
+        if let Some(rest) = cur.strip_prefix('.') {
+            let len = rest.find(['.', '[']).unwrap_or(rest.len());
+            let key = &rest[..len];
+            if key.is_empty() {
+                return Err(err());
+            }
+            ret.push(if key == "*" {
+                Step::Wildcard
+            } else {
+                Step::Key(key.to_owned())
+            });
+            cur = &rest[len..];
+        } else if let Some(rest) = cur.strip_prefix('[') {
+            let close = rest.find(']').ok_or_else(err)?;
+            let inner = rest[..close].trim();
+            ret.push(if inner == "*" {
+                Step::Wildcard
+            } else if let Ok(i) = inner.parse() {
+                Step::Index(i)
+            } else if inner.len() >= 2
+                && (inner.starts_with('\'') && inner.ends_with('\'')
+                    || inner.starts_with('"') && inner.ends_with('"'))
+            {
+                Step::Key(inner[1..inner.len() - 1].to_owned())
+            } else {
+                return Err(err());
+            });
+            cur = &rest[close + 1..];
+        } else {
+            return Err(err());
+        }
+    }
+
+    Ok(ret)
+}
+
+pub(crate) fn check_path(path: &str) -> Result<(), String> {
+    parse_path(path).map(|_| ())
+}
+
+// Selects values at `path` from JSON text.
+// Returns no values if `text` is not a valid JSON or `path` does not exist.
+pub(crate) fn select(text: &str, path: &str) -> Vec<String> {
+    let value = match serde_json::from_str::<Value>(text) {
+        Ok(value) => value,
+        Err(_) => return vec![],
+    };
+    let path = match parse_path(path) {
+        Ok(path) => path,
+        Err(_) => return vec![],
+    };
+
+    let mut cur = vec![&value];
+    for step in path.iter() {
+        let mut next = vec![];
+        for v in cur {
+            match (step, v) {
+                (Step::Key(k), Value::Object(m)) => next.extend(m.get(k)),
+                (Step::Index(i), Value::Array(a)) => next.extend(a.get(*i)),
+                (Step::Wildcard, Value::Object(m)) => next.extend(m.values()),
+                (Step::Wildcard, Value::Array(a)) => next.extend(a.iter()),
+                _ => {}
+            }
+        }
+        cur = next;
+    }
+
+    cur.into_iter()
+        .map(|v| match v {
+            Value::String(s) => s.clone(),
+            v => v.to_string(),
+        })
+        .collect()
+}
+
+#[test]
+fn test_select() {
+    let text = r#"{"offers": [{"price": 100}, {"price": "200"}], "name": "Foo"}"#;
+
+    assert_eq!(select(text, "$.name"), vec!["Foo"]);
+    assert_eq!(select(text, "$['name']"), vec!["Foo"]);
+    assert_eq!(select(text, "$.offers[0].price"), vec!["100"]);
+    assert_eq!(select(text, "$.offers[*].price"), vec!["100", "200"]);
+    assert_eq!(select(text, "$.offers[1]"), vec![r#"{"price":"200"}"#]);
+    assert!(select(text, "$.foo").is_empty());
+    assert!(select("{", "$").is_empty());
+
+    assert!(check_path("$.offers[0]").is_ok());
+    assert!(check_path("offers").is_err());
+    assert!(check_path("$.offers[").is_err());
+}
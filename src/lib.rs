@@ -342,10 +342,339 @@ this document is:
 ]
 ```
 
+## JSON
+
+The pattern `{{var:json}}` parses the text as JSON,
+and `{{var:json:path}}` selects values from it by a JSON path
+such as `$.offers.price`, `$.items[0]` or `$.items[*].name`.
+String values are captured without quotes, and other values are captured as JSON.
+
+```html
+<script type="application/ld+json">{{price:json:$.offers.price}}</script>
+```
+
+Match result for
+
+```html
+<script type="application/ld+json">
+{ "@type": "Product", "offers": { "price": "980" } }
+</script>
+```
+
+this document is:
+
+```json
+[
+    { "price": "980" }
+]
+```
+
+When the path selects multiple values, each of them is a match.
+JSON can also be a part of a text, e.g. `var data = {{data:json:$.id}};`.
+
+## Filters
+
+Captured values can be post-processed by filters written after `|`.
+Filters are applied from left to right.
+
+```html
+<span class="price">{{price|trim|number}}</span>
+```
+
+Match result for
+
+```html
+<span class="price"> ¥1,234 </span>
+```
+
+this document is:
+
+```json
+[
+    { "price": "1234" }
+]
+```
+
+Built-in filters are:
+
+* `trim`: removes white-space around the value
+* `collapse`: replaces runs of white-space with a single space, and trims the value
+* `lower`, `upper`: converts the case
+* `unescape`: decodes character references such as `&amp;`
+* `number`: extracts a number without currency symbols and thousands separators,
+  e.g. `-$1,234.50` to `-1234.50`
+
+Filters work in attributes and with other placeholders, e.g. `{{body:*|trim}}`
+or `{{price:json:$.offers.price|number}}`. If a filter rejects a value,
+e.g. `number` for a text without digits, the node does not match.
+Custom filters can be added by `PatternBuilder`.
+
+## URL
+
+The pattern `{{var:url}}` captures an absolute URL resolved against the URL of the document,
+which is given by `Pattern::matches_with_base` or `MatchOptions::base_url`.
+`<base href>` in the document is respected.
+
+```html
+<a href="{{link:url}}">{{title}}</a>
+```
+
+Match result for
+
+```html
+<a href="/about">About</a>
+```
+
+at `https://example.com/news/` is:
+
+```json
+[
+    { "link": "https://example.com/about", "title": "About" }
+]
+```
+
+Candidates with malformed URLs, or relative URLs without base URL, do not match,
+and the other matches are returned.
+
+## Compound attributes
+
+`{{var:srcset}}` captures the URL of the largest image in `srcset`,
+and `{{var:srcset:url}}` resolves it as `{{var:url}}` does.
+
+```html
+<img srcset="{{image:srcset}}">
+```
+
+Inline styles match by declarations, so the pattern can pick properties in any order.
+Quotes in `url(...)` are removed.
+
+```html
+<div style="background-image: url({{image}})"></div>
+```
+
+Placeholders in attribute names match any attributes, e.g. `data-*` attributes.
+Each of matched attributes is a match.
+
+```html
+<div data-{{key}}="{{value}}"></div>
+```
+
+Match result for
+
+```html
+<div data-id="12" data-kind="book"></div>
+```
+
+this document is:
+
+```json
+[
+    { "key": "id", "value": "12" },
+    { "key": "kind", "value": "book" }
+]
+```
+
+Attribute names are lowercased by the parser, so placeholder names in them should be lowercase.
+
+## Case and Unicode folding
+
+Texts are compared exactly by default. `PatternBuilder` can fold case,
+normalize texts by NFKC, and ignore diacritics for the whole pattern.
+They apply to literal texts, attribute values and class words.
+Captured values are kept as they are in the document.
+
+```rust
+use easy_scraper::PatternBuilder;
+
+let pat = PatternBuilder::new()
+    .case_insensitive(true)
+    .nfkc(true)
+    .build("<p>price: {{price}}</p>")
+    .unwrap();
+
+let ms = pat.matches("<p>ＰＲＩＣＥ: １００</p>");
+assert_eq!(ms[0]["price"], "１００");
+```
+
+The special attribute `fold` enables them for a node and its descendants.
+It takes `case`, `nfkc` and `diacritics`.
+
+```html
+<ul fold="case nfkc">
+    <li class="item">{{name}}</li>
+</ul>
+```
+
+## Fuzzy matching
+
+Literal texts in patterns, such as labels of tables, can be matched fuzzily.
+A text matches when the edit distance is at most the given number of characters,
+or when the ratio of common words is at least the given similarity.
+`Pattern::matches_scored` returns scores of matches, which are less than 1
+for fuzzy matches, so that you can notice changes of documents.
+
+```rust
+use easy_scraper::PatternBuilder;
+
+let pat = PatternBuilder::new()
+    .fuzzy(2, 0.8)
+    .build("<tr><th>Release date</th><td>{{date}}</td></tr>")
+    .unwrap();
+
+let ms = pat.matches_scored("<table><tr><th>Release Date:</th><td>2020-01-01</td></tr></table>");
+assert_eq!(ms[0].bindings["date"], "2020-01-01");
+assert!(ms[0].score < 1.0);
+```
+
+## Partial matches
+
+`Pattern::best_matches` finds the best partial matches, for when a part of the pattern
+no longer matches. Each node and attribute of the pattern that matches adds weight,
+and matches are ranked by the ratio of the matched weight.
+Pattern nodes and attributes which do not match are reported by their paths.
+
+```rust
+use easy_scraper::Pattern;
+
+let pat = Pattern::new(r#"<tr><th>Price</th><td class="price">{{price}}</td></tr>"#).unwrap();
+let ms = pat.best_matches(r#"<table><tr><th>Price</th><td class="cost">100</td></tr></table>"#, 3);
+
+assert_eq!(ms[0].bindings["price"], "100");
+assert_eq!(ms[0].unmatched, vec!["tr > td[class]"]);
+```
+
+## Templates
+
+`PatternLibrary` keeps fragments of patterns defined by `<template name="...">`,
+which are referenced by `<use ref="..."/>` in patterns built with the library.
+Placeholders in a fragment are prefixed by the name of the fragment,
+or by `as` attribute of `<use>`. Recursive references are errors.
+
+```rust
+use easy_scraper::PatternLibrary;
+
+let lib = PatternLibrary::new(r#"
+<template name="row"><tr><th>{{label}}</th><td>{{value}}</td></tr></template>
+"#).unwrap();
+
+let pat = lib.build(r#"
+<table>
+    <use ref="row" as="first"/>
+    <use ref="row" as="second"/>
+</table>
+"#).unwrap();
+
+let ms = pat.matches("<table><tr><th>A</th><td>1</td></tr><tr><th>B</th><td>2</td></tr></table>");
+assert_eq!(ms[0]["first.label"], "A");
+assert_eq!(ms[0]["second.value"], "2");
+```
+
+## Building patterns in Rust
+
+Patterns can be built from `Node`s without writing HTML sources,
+so that texts and attribute values need no escaping.
+`Pattern::then`, `Pattern::inside` and `Pattern::or` combine patterns
+as siblings, as descendants, and as alternatives.
+
+```rust
+use easy_scraper::{Node, Pattern};
+
+let title = Pattern::from_nodes(&[Node::element("h2").child(Node::placeholder("title"))]).unwrap();
+let price = Pattern::new(r#"<span class="price">{{price}}</span>"#).unwrap();
+let cost = Pattern::new(r#"<b class="cost">{{price}}</b>"#).unwrap();
+let card = Pattern::from_nodes(&[Node::element("div").attr("class", "card")]).unwrap();
+
+let pat = title.then(&price.or(&cost).unwrap()).unwrap().inside(&card).unwrap();
+let ms = pat.matches(r#"<div class="card"><h2>Foo</h2><b class="cost">100</b></div>"#);
+assert_eq!(ms[0]["title"], "Foo");
+assert_eq!(ms[0]["price"], "100");
+```
+
+## Printing patterns
+
+HTML parser may change patterns, e.g. by closing elements implicitly.
+`Display` of `Pattern` prints the pattern as it is parsed, which can be parsed again
+to the same pattern, and `Debug` prints the tree of nodes with their kinds.
+Alternatives combined by `or` have no syntax. They are printed one by one, separated by
+empty lines, and each of them is parsed again to the alternative.
+
+```rust
+use easy_scraper::Pattern;
+
+let pat = Pattern::new("<ul><li>{{a}}<li>{{b}}</ul>").unwrap();
+assert_eq!(pat.to_string(), "<ul>\n    <li>{{a}}</li>\n    <li>{{b}}</li>\n</ul>\n");
+```
+
+## Diagnostics
+
+`Pattern::new_with_diagnostics` also returns warnings about elements which the HTML parser
+closes, inserts or moves, e.g. a `<div>` directly in a `<table>`, which is moved out of it.
+Patterns with parse errors, such as end tags without start tags, are built with warnings
+instead of failing.
+
+```rust
+use easy_scraper::{Pattern, WarningKind};
+
+let (_, warnings) =
+    Pattern::new_with_diagnostics("<table><div>{{x}}</div><tr><td>{{y}}</td></tr></table>").unwrap();
+assert_eq!(warnings[0].kind, WarningKind::FosterParented);
+assert_eq!(warnings[0].to_string(), "1:8: <div> is moved out of <table> at 1:1");
+```
+
+## Comment
+
+Comments in patterns match comments in documents.
+You can write placeholders in comments.
+
+```html
+<li><!-- pid:{{pid}} --><a>{{title}}</a></li>
+```
+
+Match result for
+
+```html
+<li><!-- pid:123 --><a href="/a">Foo</a></li>
+<li><!-- pid:456 --><a href="/b">Bar</a></li>
+```
+
+this document is:
+
+```json
+[
+    { "pid": "123", "title": "Foo" },
+    { "pid": "456", "title": "Bar" },
+]
+```
+
+Comments in documents are ignored when matching siblings that are not comments.
+
+## Fragment
+
+Patterns are parsed as HTML fragments, so table rows, cells, list items and options
+can be top-level nodes of patterns.
+
+```html
+<tr>
+    <td>{{name}}</td>
+    <td>{{price}}</td>
+</tr>
+```
+
+`<tbody>` is not needed in table patterns. Rows in the pattern match rows
+in any section of the table.
+
+## Threads
+
+Compiled patterns are `Send` and `Sync`, so one pattern can be shared between threads.
+With the `rayon` feature, `Pattern::par_matches` matches many documents in parallel.
+
 ## White-space
 
 White-space are ignored almost all positions.
 
+Texts in `<pre>`, `<textarea>` and `<code>` are kept as they are.
+You can change how white-space in texts is handled by `WhitespaceMode`.
+
 # Restrictions
 
 * Whole sub-tree patterns must be the only one element of the parent node.
@@ -375,12 +704,49 @@ There are invalid:
 ```
 */
 
-use kuchiki::{parse_html, parse_html_with_options, Attributes, NodeRef, ParseOpts};
+use html5ever::{LocalName, Namespace, QualName};
+use kuchiki::{parse_html, parse_html_with_options, Attributes, NodeData, NodeRef, ParseOpts};
 use kuchiki::{traits::*, ExpandedName};
-use std::cell::RefCell;
-use std::collections::{BTreeMap, BTreeSet};
+use std::borrow::Cow;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::io::Read;
 use std::ops::Deref;
 use std::rc::Rc;
+use std::time::Instant;
+use url::Url;
+
+mod attr;
+mod compose;
+mod diagnostics;
+mod encoding;
+mod filter;
+mod fold;
+mod fuzzy;
+mod index;
+mod infer;
+mod json;
+mod partial;
+mod pattern;
+mod print;
+mod stream;
+mod template;
+mod view;
+mod xml;
+
+pub use compose::Node;
+pub use diagnostics::{Warning, WarningKind};
+use filter::Filters;
+use fold::Fold;
+use fuzzy::Fuzzy;
+use index::{AddrMap, Index};
+pub use partial::PartialMatch;
+use partial::PartialMatcher;
+use pattern::{PatternData, PatternNode};
+pub use stream::StreamMatches;
+pub use template::PatternLibrary;
+use view::View;
 
 /// Pattern for matching HTML document
 ///
@@ -414,719 +780,3796 @@ use std::rc::Rc;
 /// assert_eq!(ms[2]["hoge"], "3");
 /// ```
 ///
-pub struct Pattern(NodeRef);
-
-impl Pattern {
-    /// Build pattern
-    pub fn new(pattern_str: &str) -> Result<Pattern, String> {
-        let doc = filter_whitespace(parse_html_strict(pattern_str)?).unwrap();
-        Ok(Pattern(doc))
-    }
+#[derive(Clone)]
+pub struct Pattern {
+    pattern: PatternNode,
+    // The pattern is a whole document, whose top-level nodes are in `<head>` or `<body>`.
+    document: bool,
+    syntax: Syntax,
+    filters: Filters,
+    fuzzy: Option<Fuzzy>,
+    // Patterns combined by `or`
+    alternatives: Vec<Pattern>,
+}
 
-    /// Match HTML document to pattern
-    /// Returns all matches.
-    pub fn matches(&self, html: &str) -> Vec<BTreeMap<String, String>> {
-        let doc = filter_whitespace(parse_html().one(html)).unwrap();
-        match_subtree(&doc, &self.0, false)
-    }
+/// Builder of patterns with custom filters
+///
+/// Filters are applied to captured values by `{{name|filter}}`.
+/// A filter returns the new value, or `None` to reject the value.
+///
+/// ```
+/// use easy_scraper::PatternBuilder;
+///
+/// let pat = PatternBuilder::new()
+///     .filter("yen", |s| s.strip_suffix('円').map(|r| r.to_owned()))
+///     .build("<li>{{price|yen|number}}</li>")
+///     .unwrap();
+///
+/// let ms = pat.matches("<ul><li>1,200円</li><li>$3</li></ul>");
+/// assert_eq!(ms.len(), 1);
+/// assert_eq!(ms[0]["price"], "1200");
+/// ```
+#[derive(Clone, Default)]
+pub struct PatternBuilder {
+    filters: Filters,
+    fold: Fold,
+    fuzzy: Option<Fuzzy>,
+    library: PatternLibrary,
+    // Parse errors are accepted, for `Pattern::new_with_diagnostics`.
+    lenient: bool,
 }
 
-fn parse_html_strict(s: &str) -> Result<NodeRef, String> {
-    let errs = Rc::new(RefCell::new(vec![]));
-    let ret = {
-        let mut opts = ParseOpts::default();
-        let errs = Rc::clone(&errs);
-        opts.on_parse_error = Some(Box::new(move |err| {
-            // eprintln!("HTML parse error: {:?}", err);
-            if err != "Unexpected token" {
-                errs.borrow_mut().push(err.to_string())
-            }
-        }));
-        parse_html_with_options(opts).one(s)
-    };
-    let errs = errs.deref().borrow().clone();
-    let mut m = BTreeSet::new();
-    let mut errs_uniq = vec![];
-    for err in errs {
-        if !m.contains(&err) {
-            m.insert(err.clone());
-            errs_uniq.push(err);
-        }
+impl PatternBuilder {
+    /// Create builder with built-in filters
+    pub fn new() -> PatternBuilder {
+        PatternBuilder::default()
     }
 
-    if errs_uniq.is_empty() {
-        Ok(ret)
-    } else {
-        Err(errs_uniq.join(", "))
+    /// Add filter, or replace the filter of the same name
+    pub fn filter<F>(mut self, name: &str, f: F) -> PatternBuilder
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.filters.insert(name, f);
+        self
     }
-}
 
-fn match_subtree(doc: &NodeRef, pattern: &NodeRef, exact: bool) -> Vec<BTreeMap<String, String>> {
-    let mut ret = vec![];
-
-    if let (Some(_), Some(_)) = (doc.as_doctype(), pattern.as_doctype()) {
-        let doc_cs = doc.children().collect::<Vec<_>>();
-        let pat_cs = pattern.children().collect::<Vec<_>>();
-        ret.append(&mut match_siblings(&doc_cs, &pat_cs, false));
+    /// Compare texts ignoring case
+    ///
+    /// This and the other folding options apply to literal texts, attribute values
+    /// and class words in the pattern. They can be enabled for a node and its descendants
+    /// by the special attribute `fold`, e.g. `<div fold="case nfkc diacritics">`.
+    ///
+    /// ```
+    /// use easy_scraper::PatternBuilder;
+    ///
+    /// let pat = PatternBuilder::new()
+    ///     .case_insensitive(true)
+    ///     .build(r#"<tr><th>price</th><td>{{price}}</td></tr>"#)
+    ///     .unwrap();
+    ///
+    /// let ms = pat.matches("<table><tr><th>Price</th><td>100</td></tr></table>");
+    /// assert_eq!(ms[0]["price"], "100");
+    /// ```
+    pub fn case_insensitive(mut self, enabled: bool) -> PatternBuilder {
+        self.fold.case = enabled;
+        self
     }
 
-    if let (Some(_), Some(_)) = (doc.as_document(), pattern.as_document()) {
-        let doc_cs = doc.children().collect::<Vec<_>>();
-        let pat_cs = pattern.children().collect::<Vec<_>>();
-        ret.append(&mut match_siblings(&doc_cs, &pat_cs, false));
+    /// Compare texts after NFKC normalization, e.g. full-width `Ａ１` matches `A1`
+    pub fn nfkc(mut self, enabled: bool) -> PatternBuilder {
+        self.fold.nfkc = enabled;
+        self
     }
 
-    if let (Some(e1), Some(e2)) = (doc.as_element(), pattern.as_element()) {
-        if e1.name == e2.name {
-            if let Some(m1) = match_attributes(
-                e1.attributes.borrow().deref(),
-                e2.attributes.borrow().deref(),
-            ) {
-                let subseq = e2
-                    .attributes
-                    .borrow()
-                    .deref()
-                    .map
-                    .keys()
-                    .any(|k| k.local.as_ref() == "subseq");
-
-                let doc_cs = doc.children().collect::<Vec<_>>();
-                let pat_cs = pattern.children().collect::<Vec<_>>();
+    /// Compare texts ignoring diacritics, e.g. `café` matches `cafe`
+    pub fn ignore_diacritics(mut self, enabled: bool) -> PatternBuilder {
+        self.fold.diacritics = enabled;
+        self
+    }
 
-                // FIXME: this is hack for auto completion of <tbody> tag.
-                let pat_cs = if e2.name.local.as_ref() == "table"
-                    && pat_cs.len() == 1
-                    && pat_cs[0].as_element().map(|r| r.name.local.as_ref()) == Some("tbody")
-                {
-                    pat_cs[0].children().collect::<Vec<_>>()
-                } else {
-                    pat_cs
-                };
+    /// Match literal texts fuzzily
+    ///
+    /// A literal text in the pattern matches a text in the document when the edit distance
+    /// is at most `max_distance` characters, or when the ratio of common words is at least
+    /// `min_similarity`, after folding. Fuzzy matches have scores less than 1,
+    /// which are returned by [`Pattern::matches_scored`].
+    ///
+    /// `min_similarity` must be in `(0, 1]`, otherwise building patterns fails.
+    ///
+    /// ```
+    /// use easy_scraper::PatternBuilder;
+    ///
+    /// let pat = PatternBuilder::new()
+    ///     .fuzzy(2, 0.8)
+    ///     .build("<tr><th>Release date</th><td>{{date}}</td></tr>")
+    ///     .unwrap();
+    ///
+    /// let ms = pat.matches_scored("<table><tr><th>Release Date:</th><td>2020-01-01</td></tr></table>");
+    /// assert_eq!(ms[0].bindings["date"], "2020-01-01");
+    /// assert!(ms[0].score < 1.0);
+    /// ```
+    pub fn fuzzy(mut self, max_distance: usize, min_similarity: f64) -> PatternBuilder {
+        self.fuzzy = Some(Fuzzy {
+            max_distance,
+            min_similarity,
+        });
+        self
+    }
 
-                let m2 = match_siblings(&doc_cs, &pat_cs, subseq);
+    /// Expand `<use ref="..."/>` in patterns with templates of `library`
+    pub fn library(mut self, library: &PatternLibrary) -> PatternBuilder {
+        self.library = library.clone();
+        self
+    }
 
-                ret.append(&mut map_product(vec![m1], m2));
-            }
+    /// Build pattern in the same way as [`Pattern::new`]
+    pub fn build(&self, pattern_str: &str) -> Result<Pattern, String> {
+        self.check()?;
+        let pattern_str = &self.library.expand(pattern_str)?;
+        match infer_context(pattern_str) {
+            Some(context) => Pattern::parse_fragment(pattern_str, context, self),
+            None => Pattern::parse_document(pattern_str, self),
         }
     }
 
-    if let Some(pat_text) = pattern.as_text() {
-        if let Some(var) = is_var(pat_text.borrow().as_ref()) {
-            assert!(!var.whole);
+    /// Build pattern in the same way as [`Pattern::new_fragment`]
+    pub fn build_fragment(&self, pattern_str: &str, context: &str) -> Result<Pattern, String> {
+        self.check()?;
+        Pattern::parse_fragment(&self.library.expand(pattern_str)?, context, self)
+    }
 
-            if let Some(doc_text) = doc.as_text() {
-                return vec![singleton(var.name, doc_text.borrow().trim().to_owned())];
-            }
+    /// Build pattern from nodes in the same way as [`Pattern::from_nodes`]
+    pub fn build_nodes(&self, nodes: &[Node]) -> Result<Pattern, String> {
+        self.check()?;
+        let doc = filter_pattern(compose::to_tree(nodes)).unwrap();
+        check_placeholders(&doc, &self.filters)?;
+        Ok(Pattern {
+            pattern: PatternNode::new(&doc, self.fold)?,
+            document: false,
+            syntax: Syntax::Html,
+            filters: self.filters.clone(),
+            fuzzy: self.fuzzy,
+            alternatives: vec![],
+        })
+    }
 
-            return vec![];
-        }
+    /// Build pattern in the same way as [`Pattern::new_xml`]
+    pub fn build_xml(&self, pattern_str: &str) -> Result<Pattern, String> {
+        self.check()?;
+        Pattern::parse_xml(&self.library.expand(pattern_str)?, self)
+    }
 
-        if let Some(doc_text) = doc.as_text() {
-            if let Some(m) = match_text(doc_text.borrow().trim(), pat_text.borrow().trim()) {
-                return vec![m];
+    // With 0, fuzzy literals would match any text.
+    fn check(&self) -> Result<(), String> {
+        match self.fuzzy {
+            Some(fuzzy) if !(fuzzy.min_similarity > 0.0 && fuzzy.min_similarity <= 1.0) => {
+                Err(format!("Invalid min_similarity: {}", fuzzy.min_similarity))
             }
+            _ => Ok(()),
         }
+    }
+}
 
-        // Do not search recursive text pattern.
-        return vec![];
+// How patterns and documents are parsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Syntax {
+    Html,
+    Xml,
+}
+
+impl Pattern {
+    /// Build XML pattern
+    ///
+    /// Documents are parsed as XML for this pattern,
+    /// so that cases of names and namespaces are kept.
+    /// The pattern can have multiple root nodes. Elements without namespace in the pattern
+    /// match to elements with the same local name in any namespace,
+    /// and prefixed names match by namespace URI.
+    /// Prefixes `atom`, `content`, `dc`, `media`, `sitemap`, `svg`, `xhtml` and `xlink`
+    /// can be used without declarations.
+    ///
+    /// ```
+    /// use easy_scraper::Pattern;
+    ///
+    /// let pat = Pattern::new_xml(r#"
+    /// <item>
+    ///     <title>{{title}}</title>
+    ///     <dc:creator>{{author}}</dc:creator>
+    /// </item>
+    /// "#).unwrap();
+    ///
+    /// let ms = pat.matches(r#"<?xml version="1.0"?>
+    /// <rss version="2.0" xmlns:d="http://purl.org/dc/elements/1.1/">
+    ///     <channel>
+    ///         <item><title>Foo</title><d:creator>Alice</d:creator></item>
+    ///     </channel>
+    /// </rss>
+    /// "#);
+    ///
+    /// assert_eq!(ms.len(), 1);
+    /// assert_eq!(ms[0]["title"], "Foo");
+    /// assert_eq!(ms[0]["author"], "Alice");
+    /// ```
+    pub fn new_xml(pattern_str: &str) -> Result<Pattern, String> {
+        PatternBuilder::new().build_xml(pattern_str)
     }
 
-    if !exact {
-        for doc_child in doc.children() {
-            ret.append(&mut match_subtree(&doc_child, pattern, false));
-        }
+    fn parse_xml(pattern_str: &str, builder: &PatternBuilder) -> Result<Pattern, String> {
+        let doc = filter_pattern(xml::parse_xml_pattern(pattern_str)?).unwrap();
+        check_placeholders(&doc, &builder.filters)?;
+        Ok(Pattern {
+            pattern: PatternNode::new(&doc, builder.fold)?,
+            document: false,
+            syntax: Syntax::Xml,
+            filters: builder.filters.clone(),
+            fuzzy: builder.fuzzy,
+            alternatives: vec![],
+        })
     }
 
-    ret
-}
+    /// Build pattern
+    ///
+    /// The pattern is parsed as an HTML fragment. The context element is inferred
+    /// from the first tag, so that patterns starting with `<tr>`, `<td>`, `<option>` etc.
+    /// are kept as written. Patterns starting with `<!DOCTYPE>`, `<html>`, `<head>`
+    /// or `<body>` are parsed as whole documents.
+    ///
+    /// ```
+    /// use easy_scraper::Pattern;
+    ///
+    /// let pat = Pattern::new("<tr><td>{{name}}</td><td>{{price}}</td></tr>").unwrap();
+    /// let ms = pat.matches("<table><tr><td>Apple</td><td>100</td></tr></table>");
+    ///
+    /// assert_eq!(ms.len(), 1);
+    /// assert_eq!(ms[0]["name"], "Apple");
+    /// ```
+    pub fn new(pattern_str: &str) -> Result<Pattern, String> {
+        PatternBuilder::new().build(pattern_str)
+    }
 
-fn match_siblings(
-    doc: &[NodeRef],
-    pattern: &[NodeRef],
-    subseq: bool,
-) -> Vec<BTreeMap<String, String>> {
-    if pattern.is_empty() {
-        return vec![BTreeMap::new()];
+    /// Build pattern, with warnings about changes by the HTML parser
+    ///
+    /// The parser closes, inserts and moves elements to make a valid tree, which may
+    /// make the pattern different from the source and never match. Warnings point to
+    /// source locations of such elements. Parse errors, e.g. by end tags without
+    /// start tags, are also reported as warnings instead of errors.
+    ///
+    /// ```
+    /// use easy_scraper::{Pattern, WarningKind};
+    ///
+    /// let (_, warnings) = Pattern::new_with_diagnostics("<p><div>{{x}}</div>").unwrap();
+    /// assert_eq!(warnings[0].kind, WarningKind::AutoClosed);
+    /// assert_eq!((warnings[0].line, warnings[0].column), (1, 4));
+    /// assert_eq!(warnings[0].to_string(), "1:4: <p> at 1:1 is closed before <div>");
+    /// ```
+    pub fn new_with_diagnostics(pattern_str: &str) -> Result<(Pattern, Vec<Warning>), String> {
+        // Changes by the parser are reported as warnings, instead of errors.
+        let builder = PatternBuilder {
+            lenient: true,
+            ..PatternBuilder::new()
+        };
+        let pattern = builder.build(pattern_str)?;
+        // Parts of documents. The root of fragments is already removed.
+        let implied: &[&str] = if pattern.document {
+            &["html", "head", "body"]
+        } else {
+            &[]
+        };
+        let warnings = diagnostics::diagnose(pattern_str, &pattern.pattern, implied);
+        Ok((pattern, warnings))
     }
 
-    if doc.is_empty() {
-        return vec![];
+    /// Build pattern parsed as an HTML fragment in `context` element
+    ///
+    /// ```
+    /// use easy_scraper::Pattern;
+    ///
+    /// let pat = Pattern::new_fragment("<td>{{a}}</td><td>{{b}}</td>", "tr").unwrap();
+    /// let ms = pat.matches("<table><tr><td>1</td><td>2</td></tr></table>");
+    ///
+    /// assert_eq!(ms[0]["a"], "1");
+    /// assert_eq!(ms[0]["b"], "2");
+    /// ```
+    pub fn new_fragment(pattern_str: &str, context: &str) -> Result<Pattern, String> {
+        PatternBuilder::new().build_fragment(pattern_str, context)
     }
 
-    // special case: if `pattern` is whole variable, all `doc` nodes matches
-    if pattern.len() == 1 {
-        if let Some(pat_text) = pattern[0].as_text() {
-            if let Some(var) = is_var(pat_text.borrow().as_ref()) {
-                if var.whole {
-                    let texts = doc.iter().map(|r| r.to_string()).collect::<Vec<_>>();
-                    return vec![singleton(var.name, texts.concat())];
-                }
+    fn parse_fragment(
+        pattern_str: &str,
+        context: &str,
+        builder: &PatternBuilder,
+    ) -> Result<Pattern, String> {
+        let parsed = parse_html_strict(pattern_str, Some(context), builder.lenient)?;
+        remove_implied_tbody(&parsed, pattern_str);
+        let doc = filter_pattern(parsed).unwrap();
+        check_placeholders(&doc, &builder.filters)?;
+
+        // Fragment is parsed into an `<html>` element, which is not a part of the pattern.
+        for html in doc.children().collect::<Vec<_>>() {
+            for child in html.children().collect::<Vec<_>>() {
+                child.detach();
+                doc.append(child);
             }
+            html.detach();
         }
-    }
 
-    let mut ret = vec![];
+        Ok(Pattern {
+            pattern: PatternNode::new(&doc, builder.fold)?,
+            document: false,
+            syntax: Syntax::Html,
+            filters: builder.filters.clone(),
+            fuzzy: builder.fuzzy,
+            alternatives: vec![],
+        })
+    }
 
-    // 1. `pattern` nodes match consective element of `doc`
-    if subseq {
-        ret.append(&mut match_siblings_direct(&doc[..], pattern, subseq));
-    } else {
-        for i in 0..doc.len() {
-            ret.append(&mut match_siblings_direct(&doc[i..], pattern, subseq));
+    fn parse_document(pattern_str: &str, builder: &PatternBuilder) -> Result<Pattern, String> {
+        let parsed = parse_html_strict(pattern_str, None, builder.lenient)?;
+        remove_implied_tbody(&parsed, pattern_str);
+        let doc = filter_pattern(parsed).unwrap();
+        check_placeholders(&doc, &builder.filters)?;
+
+        // Comments before the first element are put out of `<html>` by the parser.
+        if let Some(body) = doc.descendants().find(|r| {
+            r.as_element()
+                .is_some_and(|e| e.name.local.as_ref() == "body")
+        }) {
+            let comments = doc.children().filter(is_comment_like).collect::<Vec<_>>();
+            for comment in comments.into_iter().rev() {
+                comment.detach();
+                body.prepend(comment);
+            }
         }
+
+        Ok(Pattern {
+            pattern: PatternNode::new(&doc, builder.fold)?,
+            document: true,
+            syntax: Syntax::Html,
+            filters: builder.filters.clone(),
+            fuzzy: builder.fuzzy,
+            alternatives: vec![],
+        })
     }
 
-    // 2. all `pattern` nodes are contained in the one `doc` node
-    for d in doc.iter() {
-        ret.append(&mut match_descendants(d, pattern, subseq));
+    /// Build pattern from nodes
+    ///
+    /// The pattern is the same as the one parsed from the source of the nodes,
+    /// whose top-level nodes are `nodes`.
+    ///
+    /// ```
+    /// use easy_scraper::{Node, Pattern};
+    ///
+    /// let pat = Pattern::from_nodes(&[Node::element("ul")
+    ///     .child(Node::element("li").child(Node::placeholder("first")))
+    ///     .child(Node::skip())
+    ///     .child(Node::element("li").child(Node::placeholder("last")))])
+    /// .unwrap();
+    ///
+    /// let ms = pat.matches("<ul><li>1</li><li>2</li><li>3</li></ul>");
+    /// assert_eq!(ms.len(), 3);
+    /// ```
+    pub fn from_nodes(nodes: &[Node]) -> Result<Pattern, String> {
+        PatternBuilder::new().build_nodes(nodes)
     }
 
-    ret
-}
+    /// Combine patterns as siblings, `self` followed by `other`
+    ///
+    /// Filters of both patterns are kept. It fails if they have different filters
+    /// of the same name.
+    ///
+    /// ```
+    /// use easy_scraper::Pattern;
+    ///
+    /// let th = Pattern::new("<th>Price</th>").unwrap();
+    /// let td = Pattern::new("<td>{{price}}</td>").unwrap();
+    /// let pat = th.then(&td).unwrap();
+    ///
+    /// let ms = pat.matches("<table><tr><th>Price</th><td>100</td></tr></table>");
+    /// assert_eq!(ms[0]["price"], "100");
+    /// ```
+    pub fn then(&self, other: &Pattern) -> Result<Pattern, String> {
+        self.compose(other, |a, b| {
+            Ok(a.roots().into_iter().chain(b.roots()).cloned().collect())
+        })
+    }
 
-// Matches two siblings.
-// * `subseq` - If true, check if `pattern` is subsequence of `doc`.
-// Otherwise, check if `pattern` is substring of `doc`.
-fn match_siblings_direct(
-    doc: &[NodeRef],
-    pattern: &[NodeRef],
-    subseq: bool,
-) -> Vec<BTreeMap<String, String>> {
-    let non_skip_len = pattern
-        .iter()
-        .filter(|r| {
-            if let Some(text) = r.as_text() {
-                !is_skip(text.borrow().as_ref())
-            } else {
-                true
+    /// Put `self` in the root element of `other`
+    ///
+    /// `self` matches to descendants of the root. If the root has children in `other`,
+    /// `self` follows them as siblings with `...` between.
+    ///
+    /// ```
+    /// use easy_scraper::Pattern;
+    ///
+    /// let card = Pattern::new(r#"<div class="card"></div>"#).unwrap();
+    /// let price = Pattern::new(r#"<span class="price">{{price}}</span>"#).unwrap();
+    /// let pat = price.inside(&card).unwrap();
+    ///
+    /// let ms = pat.matches(r#"<div class="card"><h2>Foo</h2><p><span class="price">100</span></p></div>"#);
+    /// assert_eq!(ms[0]["price"], "100");
+    /// ```
+    pub fn inside(&self, other: &Pattern) -> Result<Pattern, String> {
+        self.compose(other, |a, b| {
+            let mut root = match b.roots().as_slice() {
+                [root] if root.as_element().is_some() => (*root).clone(),
+                _ => return Err("Outer pattern needs a single root element".to_owned()),
+            };
+            if !root.children.is_empty() {
+                root.children.push(PatternNode {
+                    data: PatternData::Text("...".to_owned()),
+                    children: vec![],
+                    fold: root.fold,
+                });
             }
+            root.children.extend(a.roots().into_iter().cloned());
+            Ok(vec![root])
         })
-        .count();
+    }
 
-    if non_skip_len == 0 {
-        return vec![BTreeMap::new()];
+    /// Combine patterns as alternatives
+    ///
+    /// Matches of `self` are followed by matches of `other`.
+    ///
+    /// ```
+    /// use easy_scraper::Pattern;
+    ///
+    /// let old = Pattern::new(r#"<span class="price">{{price}}</span>"#).unwrap();
+    /// let new = Pattern::new(r#"<b class="cost">{{price}}</b>"#).unwrap();
+    /// let pat = old.or(&new).unwrap();
+    ///
+    /// let ms = pat.matches(r#"<b class="cost">100</b>"#);
+    /// assert_eq!(ms[0]["price"], "100");
+    /// ```
+    pub fn or(&self, other: &Pattern) -> Result<Pattern, String> {
+        if self.syntax != other.syntax {
+            return Err("HTML and XML patterns cannot be combined".to_owned());
+        }
+        let mut ret = self.clone();
+        ret.alternatives.extend(other.variants());
+        Ok(ret)
     }
 
-    if non_skip_len > doc.len() {
-        return vec![];
+    // Patterns combined by `or`, each without alternatives.
+    fn variants(&self) -> Vec<Pattern> {
+        let mut first = self.clone();
+        let rest = std::mem::take(&mut first.alternatives);
+        std::iter::once(first).chain(rest).collect()
     }
 
-    if let Some(text) = pattern[0].as_text() {
-        if is_skip(text.borrow().as_ref()) {
-            let mut ret = vec![];
-            for i in 0..doc.len() {
-                ret.append(&mut match_siblings_direct(&doc[i..], &pattern[1..], subseq));
+    // Combines each pair of alternatives by `f`, which returns the new top-level nodes.
+    fn compose<F>(&self, other: &Pattern, f: F) -> Result<Pattern, String>
+    where
+        F: Fn(&Pattern, &Pattern) -> Result<Vec<PatternNode>, String>,
+    {
+        if self.syntax != other.syntax {
+            return Err("HTML and XML patterns cannot be combined".to_owned());
+        }
+
+        let mut ret = vec![];
+        for a in self.variants() {
+            for b in other.variants() {
+                if a.document || b.document {
+                    return Err("Document patterns cannot be combined".to_owned());
+                }
+                let mut filters = a.filters.clone();
+                filters.extend(&b.filters)?;
+                ret.push(Pattern {
+                    pattern: PatternNode {
+                        data: PatternData::Document,
+                        children: f(&a, &b)?,
+                        fold: a.pattern.fold,
+                    },
+                    document: false,
+                    syntax: a.syntax,
+                    filters,
+                    fuzzy: a.fuzzy.or(b.fuzzy),
+                    alternatives: vec![],
+                });
             }
-            return ret;
         }
+
+        let mut first = ret.remove(0);
+        first.alternatives = ret;
+        Ok(first)
     }
 
-    let a = match_subtree(&doc[0], &pattern[0], true);
+    /// Match HTML document to pattern
+    /// Returns all matches.
+    ///
+    /// For XML patterns, documents are parsed as XML,
+    /// and no matches are returned if the document is not well-formed.
+    pub fn matches(&self, html: &str) -> Vec<BTreeMap<String, String>> {
+        self.matches_with_options(html, &MatchOptions::default())
+            .unwrap_or_default()
+    }
 
-    let mut ret = if !a.is_empty() {
-        map_product(a, match_siblings_direct(&doc[1..], &pattern[1..], subseq))
-    } else {
-        vec![]
-    };
+    /// Match HTML document to pattern with limits
+    /// Returns all matches, or an error when a limit is exceeded.
+    pub fn matches_with_options(
+        &self,
+        html: &str,
+        opts: &MatchOptions,
+    ) -> Result<Vec<BTreeMap<String, String>>, MatchError> {
+        let doc = match self.syntax {
+            Syntax::Html => parse_html().one(html),
+            Syntax::Xml => xml::parse_xml(html).map_err(MatchError::InvalidDocument)?,
+        };
+        Ok(bindings(self.match_tree(&doc, &Index::new(&doc), opts)?))
+    }
 
-    if subseq {
-        ret.append(&mut match_siblings_direct(&doc[1..], pattern, subseq));
+    /// Match HTML document to pattern with scores of matches
+    ///
+    /// The score is 1 for exact matches, and less than 1 when literal texts match
+    /// fuzzily by [`PatternBuilder::fuzzy`]. It is the product of the similarities
+    /// of fuzzily matched texts.
+    pub fn matches_scored(&self, html: &str) -> Vec<ScoredMatch> {
+        self.matches_scored_with_options(html, &MatchOptions::default())
+            .unwrap_or_default()
     }
 
-    ret
-}
+    /// Match HTML document to pattern with scores of matches and limits
+    pub fn matches_scored_with_options(
+        &self,
+        html: &str,
+        opts: &MatchOptions,
+    ) -> Result<Vec<ScoredMatch>, MatchError> {
+        let doc = match self.syntax {
+            Syntax::Html => parse_html().one(html),
+            Syntax::Xml => xml::parse_xml(html).map_err(MatchError::InvalidDocument)?,
+        };
+        Ok(self
+            .match_tree(&doc, &Index::new(&doc), opts)?
+            .into_iter()
+            .map(|m| ScoredMatch {
+                bindings: m.bindings,
+                score: m.score,
+            })
+            .collect())
+    }
 
-fn match_descendants(
-    doc: &NodeRef,
-    pattern: &[NodeRef],
-    subseq: bool,
-) -> Vec<BTreeMap<String, String>> {
-    if pattern.is_empty() {
-        return vec![BTreeMap::new()];
+    /// Match HTML document at `base_url` to pattern
+    ///
+    /// `{{name:url}}` placeholders capture absolute URLs resolved against `base_url`,
+    /// or `<base href>` in the document. Candidates with malformed URLs do not match,
+    /// and a malformed `base_url` is reported as [`MatchError::Capture`].
+    ///
+    /// ```
+    /// use easy_scraper::Pattern;
+    ///
+    /// let pat = Pattern::new(r#"<a href="{{url:url}}">{{title}}</a>"#).unwrap();
+    /// let doc = r#"<a href="/about">About</a><a href="item?id=1">Item</a>"#;
+    /// let ms = pat.matches_with_base(doc, "https://example.com/news/").unwrap();
+    ///
+    /// assert_eq!(ms[0]["url"], "https://example.com/about");
+    /// assert_eq!(ms[1]["url"], "https://example.com/news/item?id=1");
+    /// ```
+    pub fn matches_with_base(
+        &self,
+        html: &str,
+        base_url: &str,
+    ) -> Result<Vec<BTreeMap<String, String>>, MatchError> {
+        let opts = MatchOptions {
+            base_url: Some(base_url.to_owned()),
+            ..MatchOptions::default()
+        };
+        self.matches_with_options(html, &opts)
     }
 
-    let mut ret = vec![];
-    let cs = doc.children().collect::<Vec<_>>();
-    ret.append(&mut match_siblings(&cs, pattern, subseq));
-    ret
-}
+    /// Find best `k` partial matches of HTML document to pattern
+    ///
+    /// Each node and attribute of the pattern that matches adds weight to the candidate,
+    /// and the score is the ratio of the matched weight. Exact matches have score 1.
+    /// Pattern nodes and attributes which do not match are listed in `unmatched`,
+    /// so that scrapers can use the other parts when a part of the document changes.
+    ///
+    /// ```
+    /// use easy_scraper::Pattern;
+    ///
+    /// let pat = Pattern::new(r#"
+    /// <div class="item">
+    ///     <h2>{{title}}</h2>
+    ///     <span class="price">{{price}}</span>
+    /// </div>
+    /// "#).unwrap();
+    ///
+    /// let doc = r#"<div class="item"><h2>Foo</h2><b class="cost">100</b></div>"#;
+    /// assert!(pat.matches(doc).is_empty());
+    ///
+    /// let ms = pat.best_matches(doc, 1);
+    /// assert_eq!(ms[0].bindings["title"], "Foo");
+    /// assert!(ms[0].score < 1.0);
+    /// assert_eq!(ms[0].unmatched, vec!["div > span"]);
+    /// ```
+    pub fn best_matches(&self, html: &str, k: usize) -> Vec<PartialMatch> {
+        let doc = match self.syntax {
+            Syntax::Html => parse_html().one(html),
+            Syntax::Xml => match xml::parse_xml(html) {
+                Ok(doc) => doc,
+                Err(_) => return vec![],
+            },
+        };
+
+        let index = Index::new(&doc);
+        let mut ret = vec![];
+        for alt in std::iter::once(self).chain(self.alternatives.iter()) {
+            for m in alt.best_matches_variant(&doc, &index, k) {
+                if !ret.contains(&m) {
+                    ret.push(m);
+                }
+            }
+        }
+        ret.sort_by(|a, b| b.score.total_cmp(&a.score));
+        ret.truncate(k);
+        ret
+    }
 
-fn match_text(doc: &str, pat: &str) -> Option<BTreeMap<String, String>> {
-    if pat.find("{{").is_some() && pat.find("}}").is_some() {
-        // FIXME: cache regex
-        let mut re_str = String::new();
+    fn best_matches_variant(&self, doc: &NodeRef, index: &Index, k: usize) -> Vec<PartialMatch> {
+        let opts = MatchOptions::default();
+        let view = View::new(doc, &opts, self.has_comments());
+        let base = self.base_url(doc, &opts).unwrap_or_default();
+        let capture = Capture {
+            filters: &self.filters,
+            base: base.as_ref(),
+        };
+        let roots = self.roots();
+        let matcher = Matcher::new(
+            &opts,
+            capture,
+            self.fuzzy,
+            &self.pattern,
+            roots.clone(),
+            view,
+            index,
+        );
+        PartialMatcher::new(&matcher, &roots).best_matches(doc, &roots, k)
+    }
 
-        re_str += "^";
+    /// Match parsed document to pattern
+    ///
+    /// The document is parsed only once when it is matched to many patterns.
+    pub fn matches_document(&self, doc: &Document) -> Vec<BTreeMap<String, String>> {
+        self.matches_document_with_options(doc, &MatchOptions::default())
+            .unwrap_or_default()
+    }
 
-        let mut vars = vec![];
+    /// Match parsed document to pattern with limits
+    pub fn matches_document_with_options(
+        &self,
+        doc: &Document,
+        opts: &MatchOptions,
+    ) -> Result<Vec<BTreeMap<String, String>>, MatchError> {
+        match self.syntax {
+            Syntax::Html => {
+                let (tree, index) = doc.parsed();
+                Ok(bindings(self.match_tree(tree, index, opts)?))
+            }
+            Syntax::Xml => self.matches_with_options(&doc.text, opts),
+        }
+    }
 
-        let mut cur = pat;
+    /// Match document given as bytes to pattern
+    ///
+    /// The encoding is detected from BOM, `content_type` (e.g. `Content-Type` header of
+    /// HTTP response), and `<meta charset>` in the document.
+    /// Use [`Document::from_bytes`] to know the detected encoding.
+    ///
+    /// ```
+    /// use easy_scraper::Pattern;
+    ///
+    /// let pat = Pattern::new("<p>{{text}}</p>").unwrap();
+    /// let bytes = b"<meta charset=\"Shift_JIS\"><p>\x93\xfa\x96\x7b</p>";
+    ///
+    /// let ms = pat.matches_bytes(bytes, None);
+    /// assert_eq!(ms[0]["text"], "日本");
+    /// ```
+    pub fn matches_bytes(
+        &self,
+        bytes: &[u8],
+        content_type: Option<&str>,
+    ) -> Vec<BTreeMap<String, String>> {
+        self.matches_document(&Document::from_bytes(bytes, content_type))
+    }
 
-        while let Some(ix) = cur.find("{{") {
-            re_str += &cur[0..ix];
-            cur = &cur[ix + 2..];
-            let close = cur.find("}}");
-            assert!(close.is_some(), "Invalid text pattern: \"{}\"", pat);
-            let close = close.unwrap();
-            vars.push(&cur[0..close]);
-            re_str += "(.*)";
-            cur = &cur[close + 2..];
+    /// Match document read from `reader`, returning matches while reading
+    ///
+    /// Only candidates of the root element of the pattern are kept in memory,
+    /// and matches are returned when they are closed. So the pattern must have
+    /// a single root element. The encoding is detected from BOM or `<meta charset>`.
+    ///
+    /// ```
+    /// use easy_scraper::Pattern;
+    ///
+    /// let pat = Pattern::new("<li>{{item}}</li>").unwrap();
+    /// let doc = "<ul><li>1</li><li>2</li></ul>";
+    ///
+    /// let mut ms = pat.matches_reader(doc.as_bytes()).unwrap();
+    /// assert_eq!(ms.next().unwrap().unwrap()["item"], "1");
+    /// assert_eq!(ms.next().unwrap().unwrap()["item"], "2");
+    /// assert!(ms.next().is_none());
+    /// ```
+    pub fn matches_reader<R: Read>(&self, reader: R) -> Result<StreamMatches<R>, String> {
+        self.matches_reader_with_options(reader, &MatchOptions::default())
+    }
+
+    /// Match document read from `reader` with limits
+    ///
+    /// `max_results` limits the total number of matches, and the other limits are
+    /// applied to each subtree of candidates.
+    pub fn matches_reader_with_options<R: Read>(
+        &self,
+        reader: R,
+        opts: &MatchOptions,
+    ) -> Result<StreamMatches<R>, String> {
+        if self.syntax == Syntax::Xml {
+            return Err("Streaming is not supported for XML patterns".to_owned());
         }
+        if !self.alternatives.is_empty() {
+            return Err("Streaming is not supported for patterns combined by `or`".to_owned());
+        }
+        StreamMatches::new(self, reader, opts)
+    }
 
-        re_str += cur;
-        re_str += "$";
+    /// Match many HTML documents to pattern in parallel
+    ///
+    /// Results are returned in the same order as `docs`.
+    /// Available with the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn par_matches<S: AsRef<str> + Sync>(
+        &self,
+        docs: &[S],
+    ) -> Vec<Vec<BTreeMap<String, String>>> {
+        use rayon::prelude::*;
+        docs.par_iter()
+            .map(|doc| self.matches(doc.as_ref()))
+            .collect()
+    }
 
-        let re = regex::Regex::new(&re_str).unwrap();
+    // Top-level nodes of the pattern.
+    fn roots(&self) -> Vec<&PatternNode> {
+        if !self.document {
+            return self.pattern.children.iter().collect();
+        }
+        self.pattern
+            .children
+            .iter()
+            .filter(|r| r.as_element().is_some())
+            .flat_map(|html| html.children.iter())
+            .flat_map(|part| part.children.iter())
+            .collect()
+    }
+
+    // Whether the pattern has `{{name:url}}` placeholders.
+    fn has_urls(&self) -> bool {
+        self.pattern.inclusive_descendants().iter().any(|r| {
+            let values = match &r.data {
+                PatternData::Text(text) | PatternData::Comment(text) => vec![text.as_str()],
+                PatternData::Element(e) => e
+                    .attributes
+                    .map
+                    .values()
+                    .map(|v| v.value.as_str())
+                    .collect(),
+                _ => vec![],
+            };
+            values
+                .into_iter()
+                .any(|v| placeholders(v).iter().any(|var| var.url))
+        })
+    }
+
+    // Comments in documents are visible only for patterns which have comments.
+    fn has_comments(&self) -> bool {
+        self.pattern
+            .inclusive_descendants()
+            .iter()
+            .any(|r| r.is_comment_like())
+    }
 
-        if let Some(caps) = re.captures(doc) {
-            let mut ret = BTreeMap::new();
-            for i in 0..vars.len() {
-                ret.insert(vars[i].to_owned(), caps[i + 1].to_string());
+    // Base URL given by options or `<base href>` in the document.
+    fn base_url(&self, doc: &NodeRef, opts: &MatchOptions) -> Result<Option<Url>, MatchError> {
+        let mut base = parse_base_url(opts)?;
+        if self.syntax == Syntax::Html && self.has_urls() {
+            if let Some(href) = find_base_href(doc) {
+                base = document_base(&href, base.as_ref()).or(base);
             }
-            Some(ret)
-        } else {
-            None
         }
-    } else {
-        if doc == pat {
-            Some(BTreeMap::new())
+        Ok(base)
+    }
+
+    fn match_tree(
+        &self,
+        doc: &NodeRef,
+        index: &Index,
+        opts: &MatchOptions,
+    ) -> Result<Vec<Matched>, MatchError> {
+        // Duplicates are removed before `max_results` is applied,
+        // so results are not limited while matching.
+        let unlimited;
+        let match_opts = if opts.dedup == Dedup::Off {
+            opts
         } else {
-            None
+            unlimited = MatchOptions {
+                max_results: None,
+                ..opts.clone()
+            };
+            &unlimited
+        };
+
+        let mut ms = self.match_variant(doc, index, match_opts)?;
+        for alt in self.alternatives.iter() {
+            ms.extend(alt.match_variant(doc, index, match_opts)?);
         }
+        let mut ms = dedup(doc, ms, opts.dedup);
+        if let Some(n) = opts.max_results {
+            ms.truncate(n);
+        }
+        Ok(ms)
+    }
+
+    fn match_variant(
+        &self,
+        doc: &NodeRef,
+        index: &Index,
+        opts: &MatchOptions,
+    ) -> Result<Vec<Matched>, MatchError> {
+        let view = View::new(doc, opts, self.has_comments());
+        let base = self.base_url(doc, opts)?;
+        let capture = Capture {
+            filters: &self.filters,
+            base: base.as_ref(),
+        };
+        let matcher = Matcher::new(
+            opts,
+            capture,
+            self.fuzzy,
+            &self.pattern,
+            self.roots(),
+            view,
+            index,
+        );
+        matcher.match_subtree(doc, &self.pattern, false)
+    }
+
+    // Whether the pattern matches the parsed document.
+    pub(crate) fn matches_tree(&self, doc: &NodeRef) -> bool {
+        self.match_tree(doc, &Index::new(doc), &MatchOptions::default())
+            .is_ok_and(|r| !r.is_empty())
+    }
+
+    /// Infer pattern source from an example document
+    ///
+    /// `examples` are pairs of a placeholder name and a value which appears in `html`
+    /// as a text or an attribute value. The pattern is built from the smallest subtree
+    /// containing all of the values. Class words and ids which look generated are dropped,
+    /// and `...` is inserted between siblings that are not consective.
+    ///
+    /// ```
+    /// use easy_scraper::Pattern;
+    ///
+    /// let doc = r#"<ul><li><a href="/a">Foo</a></li><li><a href="/b">Bar</a></li></ul>"#;
+    /// let src = Pattern::infer(doc, &[("title", "Foo"), ("url", "/a")]).unwrap();
+    /// assert_eq!(src, "<a href=\"{{url}}\">{{title}}</a>\n");
+    ///
+    /// let ms = Pattern::new(&src).unwrap().matches(doc);
+    /// assert_eq!(ms[1]["title"], "Bar");
+    /// ```
+    pub fn infer(html: &str, examples: &[(&str, &str)]) -> Result<String, String> {
+        infer::infer(html, examples)
+    }
+
+    /// Generalize sample records into pattern source
+    ///
+    /// `samples` are elements which represent the same kind of record.
+    /// Differing texts and attribute values become placeholders, class words are reduced
+    /// to the common ones, and `...` or `subseq` is inserted where the children differ.
+    ///
+    /// ```
+    /// use easy_scraper::Pattern;
+    /// use kuchiki::traits::TendrilSink;
+    ///
+    /// let doc = kuchiki::parse_html().one(
+    ///     r#"<li class="item new"><b>Foo</b><i>100</i></li>
+    ///        <li class="item"><b>Bar</b><u>sale</u><i>200</i></li>"#,
+    /// );
+    /// let samples = doc
+    ///     .select("li")
+    ///     .unwrap()
+    ///     .map(|r| r.as_node().clone())
+    ///     .collect::<Vec<_>>();
+    ///
+    /// let src = Pattern::generalize(&samples).unwrap();
+    /// assert_eq!(
+    ///     src,
+    ///     "<li class=\"item\">\n    <b>{{b}}</b>\n    ...\n    <i>{{i}}</i>\n</li>\n"
+    /// );
+    /// ```
+    pub fn generalize(samples: &[NodeRef]) -> Result<String, String> {
+        infer::generalize(samples)
     }
 }
 
-fn map_product(
-    a: Vec<BTreeMap<String, String>>,
-    b: Vec<BTreeMap<String, String>>,
-) -> Vec<BTreeMap<String, String>> {
-    let mut ret = vec![];
-    for a in a {
-        for b in b.iter() {
-            let mut a = a.clone();
-            a.append(&mut b.clone());
-            ret.push(a);
+/// Document to match patterns
+///
+/// ```
+/// use easy_scraper::{Document, Pattern};
+///
+/// let bytes = b"<p>\xc6\xfc\xcb\xdc</p>";
+/// let doc = Document::from_bytes(bytes, Some("text/html; charset=EUC-JP"));
+/// assert_eq!(doc.encoding(), "EUC-JP");
+///
+/// let ms = Pattern::new("<p>{{text}}</p>").unwrap().matches_document(&doc);
+/// assert_eq!(ms[0]["text"], "日本");
+/// ```
+pub struct Document {
+    text: String,
+    encoding: &'static encoding_rs::Encoding,
+    // Parsed and indexed lazily, since XML patterns do not use them.
+    parsed: OnceCell<(NodeRef, Index)>,
+}
+
+impl Document {
+    /// Document from text
+    pub fn new(text: &str) -> Document {
+        Document {
+            text: text.to_owned(),
+            encoding: encoding_rs::UTF_8,
+            parsed: OnceCell::new(),
         }
     }
-    ret
-}
 
-struct Variable {
-    name: String,
-    whole: bool,
+    /// Document from bytes
+    ///
+    /// The encoding is detected from BOM, `content_type`, and `<meta charset>` or
+    /// XML declaration at the head of the document, in this order.
+    /// Documents without any of them are decoded as UTF-8 if possible,
+    /// and as windows-1252 otherwise.
+    pub fn from_bytes(bytes: &[u8], content_type: Option<&str>) -> Document {
+        let (text, encoding) = encoding::decode(bytes, content_type);
+        Document {
+            text,
+            encoding,
+            parsed: OnceCell::new(),
+        }
+    }
+
+    /// Name of the encoding of the document, e.g. `"Shift_JIS"`
+    pub fn encoding(&self) -> &'static str {
+        self.encoding.name()
+    }
+
+    /// Decoded text of the document
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn parsed(&self) -> &(NodeRef, Index) {
+        self.parsed.get_or_init(|| {
+            let tree = parse_html().one(self.text.as_str());
+            let index = Index::new(&tree);
+            (tree, index)
+        })
+    }
 }
 
-fn is_var(s: &str) -> Option<Variable> {
-    let s = s.trim();
-    if s.starts_with("{{") && s.ends_with("}}") {
-        let var = &s[2..s.len() - 2];
-        let mut it = var.split(':');
-        let var = it.next()?;
+fn check_placeholders(pattern: &NodeRef, filters: &Filters) -> Result<(), String> {
+    let mut values = vec![];
+    for node in pattern.descendants() {
+        match node.data() {
+            NodeData::Text(text) | NodeData::Comment(text) => values.push(text.borrow().clone()),
+            NodeData::Element(e) => {
+                for (name, attr) in e.attributes.borrow().map.iter() {
+                    values.push(name.local.to_string());
+                    values.push(attr.value.clone());
+                }
+            }
+            _ => {}
+        }
+    }
 
-        if let Some(qual) = it.next() {
-            if qual == "*" {
-                Some(Variable {
-                    name: var.to_owned(),
-                    whole: true,
-                })
-            } else {
-                None
+    for value in values {
+        for var in placeholders(&value) {
+            if let Some(path) = &var.json {
+                json::check_path(path)?;
+            }
+            if let Some(f) = var.filters.iter().find(|r| !filters.contains(r)) {
+                return Err(format!("Unknown filter: \"{}\"", f));
             }
-        } else {
-            Some(Variable {
-                name: var.to_owned(),
-                whole: false,
-            })
         }
-    } else {
-        None
     }
+    Ok(())
 }
 
-fn is_skip(s: &str) -> bool {
-    s.trim() == "..."
+// Placeholders in a text or an attribute value.
+fn placeholders(s: &str) -> Vec<Variable> {
+    let mut ret = vec![];
+    let mut rest = s;
+    while let Some(i) = rest.find("{{") {
+        let close = match rest[i..].find("}}") {
+            Some(close) => i + close,
+            None => break,
+        };
+        ret.extend(Variable::parse(&rest[i + 2..close]));
+        rest = &rest[close + 2..];
+    }
+    ret
 }
 
-fn is_special_attr(n: &ExpandedName) -> bool {
-    let s = n.local.as_ref();
-    s == "subseq"
+fn parse_base_url(opts: &MatchOptions) -> Result<Option<Url>, MatchError> {
+    match &opts.base_url {
+        Some(base) => Url::parse(base)
+            .map(Some)
+            .map_err(|e| MatchError::Capture(format!("invalid base URL \"{}\": {}", base, e))),
+        None => Ok(None),
+    }
 }
 
-fn singleton(key: String, val: String) -> BTreeMap<String, String> {
-    let mut ret = BTreeMap::new();
-    ret.insert(key, val);
-    ret
+// `href` of the first `<base>` element.
+fn find_base_href(doc: &NodeRef) -> Option<String> {
+    doc.descendants().find_map(|r| {
+        let e = r.as_element()?;
+        if e.name.local.as_ref() != "base" {
+            return None;
+        }
+        e.attributes.borrow().get("href").map(|r| r.to_owned())
+    })
 }
 
-fn match_attributes(a1: &Attributes, a2: &Attributes) -> Option<BTreeMap<String, String>> {
-    let a1 = &a1.map;
-    let a2 = &a2.map;
-
-    let mut ret = BTreeMap::new();
+// Base URL of the document given by `<base href>`.
+// Invalid `href` is ignored as browsers do.
+fn document_base(href: &str, base: Option<&Url>) -> Option<Url> {
+    match base {
+        Some(base) => base.join(href).ok(),
+        None => Url::parse(href).ok(),
+    }
+}
 
-    for (k2, v2) in a2.iter() {
-        if is_special_attr(k2) {
+// Context element to parse the pattern in, inferred from its first tag.
+// Returns `None` if the pattern is a whole document.
+fn infer_context(src: &str) -> Option<&'static str> {
+    let mut rest = src;
+    while let Some(i) = rest.find('<') {
+        rest = &rest[i + 1..];
+        if let Some(r) = rest.strip_prefix("!--") {
+            rest = r.find("-->").map_or("", |j| &r[j + 3..]);
             continue;
         }
 
-        if let Some(v1) = a1.get(k2) {
-            if let Some(var) = is_var(&v2.value) {
-                // Simple variable
-                assert!(!var.whole);
-                ret.insert(var.name, v1.value.trim().to_owned());
-            } else if {
-                let x = v2.value.find("{{");
-                let y = v2.value.find("}}");
-                x.is_some() && y.is_some() && x < y
-            } {
-                // Complex pattern
-                let t = match_text(&v1.value, &v2.value);
-                if t.is_none() {
-                    return None;
-                }
-                ret.append(&mut t.unwrap())
-            } else if !is_subset(&v1.value, &v2.value) {
-                // Set of attribute
-                return None;
+        let name = rest
+            .split(|c: char| !c.is_ascii_alphanumeric() && c != '!')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        return match name.as_str() {
+            // end tags and stray `<`
+            "" => continue,
+            "!doctype" | "html" | "head" | "body" => None,
+            "tr" => Some("tbody"),
+            "td" | "th" => Some("tr"),
+            "tbody" | "thead" | "tfoot" | "caption" | "colgroup" => Some("table"),
+            "col" => Some("colgroup"),
+            "option" | "optgroup" => Some("select"),
+            _ => Some("body"),
+        };
+    }
+    Some("body")
+}
+
+// The parser inserts `<tbody>` around rows written directly in tables. Such `<tbody>` is
+// removed, so that the rows match rows in any section of the table.
+fn remove_implied_tbody(pattern: &NodeRef, src: &str) {
+    for node in diagnostics::inserted_elements(src, pattern) {
+        if node
+            .as_element()
+            .is_some_and(|e| e.name.local.as_ref() == "tbody")
+        {
+            for child in node.children().collect::<Vec<_>>() {
+                node.insert_before(child);
             }
-        } else {
-            return None;
+            node.detach();
         }
     }
+}
+
+const HTML_NS: &str = "http://www.w3.org/1999/xhtml";
 
-    Some(ret)
+// kuchiki does not export its sink constructor, nor `parse_fragment_with_options`,
+// so take the sink out of a document parser.
+fn new_sink(opts: ParseOpts) -> kuchiki::Sink {
+    parse_html_with_options(opts).tokenizer.sink.sink
 }
 
-fn is_subset(s1: &str, s2: &str) -> bool {
-    let ws1 = s1.split_whitespace().collect::<Vec<_>>();
-    for w in s2.split_whitespace() {
-        if !ws1.contains(&w) {
-            return false;
+// Parses `s` as a document, or as a fragment in `context` element.
+// Parse errors fail unless `lenient`.
+fn parse_html_strict(s: &str, context: Option<&str>, lenient: bool) -> Result<NodeRef, String> {
+    let errs = Rc::new(RefCell::new(vec![]));
+    let ret = {
+        let mut opts = ParseOpts::default();
+        let errs = Rc::clone(&errs);
+        let fragment = context.is_some();
+        opts.on_parse_error = Some(Box::new(move |err| {
+            // eprintln!("HTML parse error: {:?}", err);
+            // Spaces between rows are reported in table contexts, since the root of the
+            // fragment is not a table element.
+            if err != "Unexpected token" && !(fragment && err == "Unexpected characters in table") {
+                errs.borrow_mut().push(err.to_string())
+            }
+        }));
+        match context {
+            None => parse_html_with_options(opts).one(s),
+            Some(context) => {
+                let ctx = QualName::new(None, Namespace::from(HTML_NS), LocalName::from(context));
+                html5ever::parse_fragment(new_sink(opts), Default::default(), ctx, vec![]).one(s)
+            }
+        }
+    };
+    let errs = errs.deref().borrow().clone();
+    let mut m = BTreeSet::new();
+    let mut errs_uniq = vec![];
+    for err in errs {
+        if !m.contains(&err) {
+            m.insert(err.clone());
+            errs_uniq.push(err);
         }
     }
-    true
+
+    if errs_uniq.is_empty() || lenient {
+        Ok(ret)
+    } else {
+        Err(errs_uniq.join(", "))
+    }
 }
 
-fn filter_whitespace(node: NodeRef) -> Option<NodeRef> {
-    if let Some(dt) = node.as_doctype() {
-        assert!(node.first_child().is_none());
+/// Limits for matching
+///
+/// Patterns with `...` or `subseq` over long sibling lists can produce
+/// a huge number of intermediate results. These limits bound the work of
+/// [`Pattern::matches_with_options`]. All limits are disabled by default.
+///
+/// ```
+/// use easy_scraper::{MatchOptions, Pattern};
+///
+/// let pat = Pattern::new("<ul><li>{{a}}</li>...<li>{{b}}</li></ul>").unwrap();
+/// let doc = "<ul><li>1</li><li>2</li><li>3</li></ul>";
+///
+/// let opts = MatchOptions {
+///     max_results: Some(2),
+///     ..MatchOptions::default()
+/// };
+/// let ms = pat.matches_with_options(doc, &opts).unwrap();
+/// assert_eq!(ms.len(), 2);
+/// ```
+#[derive(Clone, Debug)]
+pub struct MatchOptions {
+    /// Maximum number of results. Matching stops and returns the first `max_results` results.
+    pub max_results: Option<usize>,
+    /// Maximum depth of recursion of the matcher.
+    pub max_depth: Option<usize>,
+    /// Maximum number of node comparisons.
+    pub max_steps: Option<usize>,
+    /// Matching fails when it does not finish until this time.
+    pub deadline: Option<Instant>,
+    /// How to remove duplicated results. It is applied before `max_results`.
+    pub dedup: Dedup,
+    /// How to handle white-space in texts.
+    pub whitespace: WhitespaceMode,
+    /// Elements whose texts are kept as they are.
+    /// `pre`, `textarea` and `code` by default.
+    pub preserve_elements: Vec<String>,
+    /// URL of the document, against which `{{name:url}}` captures are resolved.
+    /// `<base href>` in the document is respected.
+    pub base_url: Option<String>,
+}
 
-        Some(NodeRef::new_doctype(&dt.name, &dt.public_id, &dt.system_id))
-    } else if let Some(_) = node.as_document() {
-        let ret = NodeRef::new_document();
-        for child in node.children() {
-            if let Some(child) = filter_whitespace(child) {
-                ret.append(child);
-            }
+impl Default for MatchOptions {
+    fn default() -> MatchOptions {
+        MatchOptions {
+            max_results: None,
+            max_depth: None,
+            max_steps: None,
+            deadline: None,
+            dedup: Dedup::default(),
+            whitespace: WhitespaceMode::default(),
+            preserve_elements: default_preserve_elements(),
+            base_url: None,
         }
-        Some(ret)
-    } else if let Some(element) = node.as_element() {
-        let ret = NodeRef::new_element(
-            element.name.clone(),
-            element.attributes.borrow().map.clone(),
-        );
+    }
+}
 
-        for child in node.children() {
-            if let Some(child) = filter_whitespace(child) {
-                ret.append(child);
-            }
+/// Deduplication mode of match results
+///
+/// A document can match to a pattern through different paths.
+/// For example, `<div><span>{{x}}</span></div>` matches
+/// `<div><span><span>a</span></span></div>` twice
+/// by the outer `span` and the inner `span`.
+///
+/// ```
+/// use easy_scraper::{Dedup, MatchOptions, Pattern};
+///
+/// let pat = Pattern::new("<div><span>{{x}}</span></div>").unwrap();
+/// let doc = "<div><span><span>a</span></span></div>";
+/// assert_eq!(pat.matches(doc).len(), 2);
+///
+/// let opts = MatchOptions {
+///     dedup: Dedup::Identical,
+///     ..MatchOptions::default()
+/// };
+/// assert_eq!(pat.matches_with_options(doc, &opts).unwrap().len(), 1);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dedup {
+    /// Keep all results.
+    #[default]
+    Off,
+    /// Collapse results which have the same bindings and
+    /// whose root nodes of the pattern are matched to the same document nodes.
+    Identical,
+    /// In addition to `Identical`, drop results which overlap with
+    /// a preceding result, like `find_iter` of regular expressions.
+    /// Results are in document order and outer matches are preferred.
+    NonOverlapping,
+}
+
+/// Error for exceeding limits in [`MatchOptions`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MatchError {
+    /// `max_depth` is exceeded
+    TooDeep,
+    /// `max_steps` is exceeded
+    TooManySteps,
+    /// `deadline` is passed
+    Timeout,
+    /// The document can not be parsed
+    InvalidDocument(String),
+    /// The document can not be read
+    Io(String),
+    /// The base URL for `{{name:url}}` is malformed
+    Capture(String),
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchError::TooDeep => write!(f, "maximum recursion depth exceeded"),
+            MatchError::TooManySteps => write!(f, "maximum number of steps exceeded"),
+            MatchError::Timeout => write!(f, "deadline exceeded"),
+            MatchError::InvalidDocument(err) => write!(f, "invalid document: {}", err),
+            MatchError::Io(err) => write!(f, "I/O error: {}", err),
+            MatchError::Capture(err) => write!(f, "capture error: {}", err),
         }
+    }
+}
 
-        Some(ret)
-    } else if let Some(text) = node.as_text() {
-        assert!(node.first_child().is_none());
+impl std::error::Error for MatchError {}
 
-        let text = text.borrow();
-        let text = text.trim();
+type MatchResult = Result<Vec<Matched>, MatchError>;
 
-        if text == "" {
-            None
-        } else {
-            Some(NodeRef::new_text(text.to_owned()))
+/// Match with its score
+///
+/// Returned by [`Pattern::matches_scored`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoredMatch {
+    pub bindings: BTreeMap<String, String>,
+    /// 1 for exact matches, and less than 1 for fuzzy matches
+    pub score: f64,
+}
+
+// Bindings of a match, and document nodes matched to root nodes of the pattern.
+#[derive(Clone)]
+struct Matched {
+    bindings: BTreeMap<String, String>,
+    anchors: Vec<NodeRef>,
+    score: f64,
+}
+
+impl Default for Matched {
+    fn default() -> Matched {
+        BTreeMap::new().into()
+    }
+}
+
+impl From<BTreeMap<String, String>> for Matched {
+    fn from(bindings: BTreeMap<String, String>) -> Matched {
+        Matched {
+            bindings,
+            anchors: vec![],
+            score: 1.0,
+        }
+    }
+}
+
+fn bindings(ms: Vec<Matched>) -> Vec<BTreeMap<String, String>> {
+    ms.into_iter().map(|m| m.bindings).collect()
+}
+
+struct Matcher<'a> {
+    opts: &'a MatchOptions,
+    capture: Capture<'a>,
+    roots: Vec<&'a PatternNode>,
+    view: View,
+    index: &'a Index,
+    fuzzy: Option<Fuzzy>,
+    // Candidates of pattern nodes in `index`
+    cands: AddrMap<PatternNode, Option<&'a [usize]>>,
+    depth: Cell<usize>,
+    steps: Cell<usize>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(
+        opts: &'a MatchOptions,
+        capture: Capture<'a>,
+        fuzzy: Option<Fuzzy>,
+        pattern: &'a PatternNode,
+        roots: Vec<&'a PatternNode>,
+        view: View,
+        index: &'a Index,
+    ) -> Matcher<'a> {
+        let mut cands = AddrMap::default();
+        for node in pattern.inclusive_descendants() {
+            cands.insert(node as *const _, index.candidates(node));
+        }
+
+        Matcher {
+            opts,
+            capture,
+            roots,
+            view,
+            index,
+            fuzzy,
+            cands,
+            depth: Cell::new(0),
+            steps: Cell::new(0),
+        }
+    }
+
+    // Counts a step of matching.
+    fn step(&self) -> Result<(), MatchError> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        if self.opts.max_steps.is_some_and(|n| steps > n) {
+            return Err(MatchError::TooManySteps);
+        }
+        if steps % 1024 == 0 && self.opts.deadline.is_some_and(|d| Instant::now() > d) {
+            return Err(MatchError::Timeout);
+        }
+        Ok(())
+    }
+
+    // Enters a recursion level. Must be paired with `leave`.
+    fn enter(&self) -> Result<(), MatchError> {
+        self.step()?;
+        let depth = self.depth.get() + 1;
+        if self.opts.max_depth.is_some_and(|n| depth > n) {
+            return Err(MatchError::TooDeep);
+        }
+        self.depth.set(depth);
+        Ok(())
+    }
+
+    fn leave(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    // Appends results, keeping at most `max_results`.
+    // Since results are concatenated or multiplied in order,
+    // truncating intermediate results does not change the first `max_results` results.
+    fn append(&self, ret: &mut Vec<Matched>, mut rs: Vec<Matched>) {
+        ret.append(&mut rs);
+        if let Some(n) = self.opts.max_results {
+            ret.truncate(n);
+        }
+    }
+
+    // Pattern texts are trimmed on parsing, and compared in the same way as documents.
+    fn normalize_pattern(&self, s: &str) -> String {
+        match self.opts.whitespace {
+            WhitespaceMode::Preserve => s.to_owned(),
+            mode => normalize_whitespace(s, mode),
+        }
+    }
+
+    fn candidates(&self, pattern: &PatternNode) -> Option<&'a [usize]> {
+        match self.cands.get(&(pattern as *const _)) {
+            Some(cs) => *cs,
+            None => self.index.candidates(pattern),
+        }
+    }
+
+    // Whether `pattern` can match to a descendant of `doc`, or `doc` itself if `inclusive`.
+    fn may_contain(&self, doc: &NodeRef, pattern: &PatternNode, inclusive: bool) -> bool {
+        match self.candidates(pattern) {
+            Some(cs) => self.index.contains(doc, cs, inclusive),
+            None => true,
+        }
+    }
+
+    fn match_subtree(&self, doc: &NodeRef, pattern: &PatternNode, exact: bool) -> MatchResult {
+        self.enter()?;
+        let ret = self.match_subtree_inner(doc, pattern, exact);
+        self.leave();
+        ret
+    }
+
+    fn match_subtree_inner(
+        &self,
+        doc: &NodeRef,
+        pattern: &PatternNode,
+        exact: bool,
+    ) -> MatchResult {
+        let mut ret = self.match_node(doc, pattern)?;
+
+        if self.roots.iter().any(|r| std::ptr::eq(*r, pattern)) {
+            for m in ret.iter_mut() {
+                m.anchors.insert(0, doc.clone());
+            }
+        }
+
+        // Do not search recursive text pattern.
+        if !exact && pattern.as_text().is_none() && !pattern.is_comment_like() {
+            for doc_child in self.view.children(doc) {
+                if !self.may_contain(&doc_child, pattern, true) {
+                    continue;
+                }
+                self.append(&mut ret, self.match_subtree(&doc_child, pattern, false)?);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    // Matches `pattern` to `doc` itself.
+    fn match_node(&self, doc: &NodeRef, pattern: &PatternNode) -> MatchResult {
+        let mut ret = vec![];
+
+        if doc.as_doctype().is_some() && pattern.is_doctype() {
+            let doc_cs = self.view.children(doc);
+            self.append(
+                &mut ret,
+                self.match_siblings(&doc_cs, &pattern.children, false)?,
+            );
+        }
+
+        if doc.as_document().is_some() && pattern.is_document() {
+            let doc_cs = self.view.children(doc);
+            self.append(
+                &mut ret,
+                self.match_siblings(&doc_cs, &pattern.children, false)?,
+            );
+        }
+
+        if let (Some(e1), Some(e2)) = (doc.as_element(), pattern.as_element()) {
+            if match_name(&e1.name, &e2.name) {
+                let m1 = match_attributes(
+                    e1.attributes.borrow().deref(),
+                    &e2.attributes,
+                    pattern.fold,
+                    &self.capture,
+                );
+                if !m1.is_empty() {
+                    let subseq = e2
+                        .attributes
+                        .map
+                        .keys()
+                        .any(|k| k.local.as_ref() == "subseq");
+
+                    let doc_cs = self.view.children(doc);
+                    let m2 = self.match_siblings(&doc_cs, &pattern.children, subseq)?;
+
+                    let m1 = m1.into_iter().map(Matched::from).collect();
+                    let m = self.map_product(m1, m2)?;
+                    self.append(&mut ret, m);
+                }
+            }
+        }
+
+        if let Some(pat_text) = pattern.as_text() {
+            if let Some(var) = is_var(pat_text) {
+                assert!(!var.whole);
+
+                if let Some(doc_text) = self.view.text(doc) {
+                    return Ok(var
+                        .capture(&doc_text, &self.capture)
+                        .into_iter()
+                        .map(Matched::from)
+                        .collect());
+                }
+
+                return Ok(vec![]);
+            }
+
+            if let Some(doc_text) = self.view.text(doc) {
+                let pat_text = self.normalize_pattern(pat_text);
+                let ms = match_text(&doc_text, &pat_text, pattern.fold, &self.capture);
+                if !ms.is_empty() {
+                    return Ok(ms.into_iter().map(Matched::from).collect());
+                }
+                // Only texts without placeholders are matched fuzzily.
+                if let Some(fuzzy) = self.fuzzy.filter(|_| !has_placeholder(&pat_text)) {
+                    let fold = pattern.fold;
+                    if let Some(score) = fuzzy.score(&fold.apply(&doc_text), &fold.apply(&pat_text))
+                    {
+                        return Ok(vec![Matched {
+                            score,
+                            ..Matched::default()
+                        }]);
+                    }
+                }
+            }
+
+            return Ok(vec![]);
+        }
+
+        if let (Some(doc_text), Some(pat_text)) = (doc.as_comment(), pattern.as_comment()) {
+            return self.match_comment(&doc_text.borrow(), pat_text, pattern.fold);
+        }
+
+        if let (
+            NodeData::ProcessingInstruction(d),
+            PatternData::ProcessingInstruction(target, data),
+        ) = (doc.data(), &pattern.data)
+        {
+            let d = d.borrow();
+            if d.0 == *target {
+                return self.match_comment(&d.1, data, pattern.fold);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    fn match_comment(&self, doc: &str, pat: &str, fold: Fold) -> MatchResult {
+        let doc = normalize_whitespace(doc, WhitespaceMode::Trim);
+        let doc = self.normalize_pattern(&doc);
+        if let Some(var) = is_var(pat) {
+            if !var.whole {
+                return Ok(var
+                    .capture(&doc, &self.capture)
+                    .into_iter()
+                    .map(Matched::from)
+                    .collect());
+            }
+        }
+        Ok(match_text(
+            &doc,
+            &self.normalize_pattern(pat.trim()),
+            fold,
+            &self.capture,
+        )
+        .into_iter()
+        .map(Matched::from)
+        .collect())
+    }
+
+    fn match_siblings(
+        &self,
+        doc: &[NodeRef],
+        pattern: &[PatternNode],
+        subseq: bool,
+    ) -> MatchResult {
+        if pattern.is_empty() {
+            return Ok(vec![Matched::default()]);
+        }
+
+        if doc.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // special case: if `pattern` is whole variable, all `doc` nodes matches
+        if pattern.len() == 1 {
+            if let Some(pat_text) = pattern[0].as_text() {
+                if let Some(var) = is_var(pat_text) {
+                    if var.whole {
+                        let texts = doc.iter().map(|r| self.view.to_html(r)).collect::<Vec<_>>();
+                        return Ok(var
+                            .capture(&texts.concat(), &self.capture)
+                            .into_iter()
+                            .map(Matched::from)
+                            .collect());
+                    }
+                }
+            }
+        }
+
+        let mut ret = vec![];
+
+        // 1. `pattern` nodes match consective element of `doc`
+        let mut sibs = Siblings::new(doc, pattern, subseq);
+        if subseq {
+            self.append(&mut ret, self.match_siblings_direct(&mut sibs, 0, 0)?);
+        } else {
+            let cands = self.candidates(&pattern[0]);
+            for (i, d) in doc.iter().enumerate() {
+                if is_transparent(d, &pattern[0])
+                    || cands.is_some_and(|cs| !self.index.is_candidate(d, cs))
+                {
+                    continue;
+                }
+                self.append(&mut ret, self.match_siblings_direct(&mut sibs, i, 0)?);
+            }
+        }
+
+        // 2. all `pattern` nodes are contained in the one `doc` node
+        for d in doc.iter() {
+            if !pattern.iter().all(|p| self.may_contain(d, p, false)) {
+                continue;
+            }
+            self.append(&mut ret, self.match_descendants(d, pattern, subseq)?);
+        }
+
+        Ok(ret)
+    }
+
+    // Matches `pattern[j..]` to `doc[i..]` directly, memoized for each `(i, j)`.
+    // If `subseq`, check if the pattern is subsequence of the document.
+    // Otherwise, check if the pattern is prefix of the document.
+    fn match_siblings_direct(&self, sibs: &mut Siblings, i: usize, j: usize) -> MatchResult {
+        if let Some(ms) = sibs.direct.get(&(i, j)).filter(|_| sibs.memo) {
+            return Ok(ms.clone());
+        }
+        self.enter()?;
+        let ret = self.match_siblings_direct_inner(sibs, i, j);
+        self.leave();
+        let ret = ret?;
+        if sibs.memo {
+            sibs.direct.insert((i, j), ret.clone());
+        }
+        Ok(ret)
+    }
+
+    fn match_siblings_direct_inner(&self, sibs: &mut Siblings, i: usize, j: usize) -> MatchResult {
+        let (doc, pattern) = (sibs.doc, sibs.pattern);
+
+        if sibs.rest[j] == 0 {
+            return Ok(vec![Matched::default()]);
+        }
+
+        if sibs.rest[j] > doc.len() - i {
+            return Ok(vec![]);
+        }
+
+        let mut ret = vec![];
+
+        if let Some(text) = pattern[j].as_text() {
+            if is_skip(text) {
+                for (k, d) in doc.iter().enumerate().skip(i) {
+                    if j + 1 < pattern.len() && is_transparent(d, &pattern[j + 1]) {
+                        continue;
+                    }
+                    self.append(&mut ret, self.match_siblings_direct(sibs, k, j + 1)?);
+                }
+                return Ok(ret);
+            }
+        }
+
+        // Nodes skipped by `subseq` or transparent ones are iterated here
+        // instead of recursion, so that recursion does not get deep for long siblings.
+        for k in i..doc.len() {
+            if sibs.rest[j] > doc.len() - k {
+                break;
+            }
+            if !sibs.subseq && is_transparent(&doc[k], &pattern[j]) {
+                continue;
+            }
+
+            let a = match sibs.subtrees.get(&(k, j)) {
+                Some(a) => a.clone(),
+                None => {
+                    let a = self.match_subtree(&doc[k], &pattern[j], true)?;
+                    if sibs.memo {
+                        sibs.subtrees.insert((k, j), a.clone());
+                    }
+                    a
+                }
+            };
+            if !a.is_empty() {
+                let b = self.match_siblings_direct(sibs, k + 1, j + 1)?;
+                let m = self.map_product(a, b)?;
+                self.append(&mut ret, m);
+            }
+
+            if !sibs.subseq {
+                break;
+            }
+        }
+
+        Ok(ret)
+    }
+
+    fn match_descendants(
+        &self,
+        doc: &NodeRef,
+        pattern: &[PatternNode],
+        subseq: bool,
+    ) -> MatchResult {
+        if pattern.is_empty() {
+            return Ok(vec![Matched::default()]);
+        }
+
+        let cs = self.view.children(doc);
+        self.match_siblings(&cs, pattern, subseq)
+    }
+
+    fn map_product(&self, a: Vec<Matched>, b: Vec<Matched>) -> MatchResult {
+        let mut ret = vec![];
+        for a in a {
+            for b in b.iter() {
+                if self.opts.max_results.is_some_and(|n| ret.len() >= n) {
+                    return Ok(ret);
+                }
+                self.step()?;
+
+                let mut a = a.clone();
+                a.bindings.append(&mut b.bindings.clone());
+                a.anchors.extend(b.anchors.iter().cloned());
+                a.score *= b.score;
+                ret.push(a);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+// Siblings being matched, with memoized results for pairs of positions.
+struct Siblings<'b> {
+    doc: &'b [NodeRef],
+    pattern: &'b [PatternNode],
+    subseq: bool,
+    // Number of nodes other than `...` in `pattern[j..]`
+    rest: Vec<usize>,
+    // Positions are visited only once without `subseq` or `...`
+    memo: bool,
+    // Results of `match_siblings_direct` for `(i, j)`
+    direct: HashMap<(usize, usize), Vec<Matched>>,
+    // Results of matching `pattern[j]` to `doc[i]` exactly
+    subtrees: HashMap<(usize, usize), Vec<Matched>>,
+}
+
+impl<'b> Siblings<'b> {
+    fn new(doc: &'b [NodeRef], pattern: &'b [PatternNode], subseq: bool) -> Siblings<'b> {
+        let mut rest = vec![0; pattern.len() + 1];
+        for j in (0..pattern.len()).rev() {
+            let skip = pattern[j].as_text().is_some_and(is_skip);
+            rest[j] = rest[j + 1] + if skip { 0 } else { 1 };
+        }
+
+        Siblings {
+            doc,
+            pattern,
+            subseq,
+            memo: subseq || rest[0] < pattern.len(),
+            rest,
+            direct: HashMap::new(),
+            subtrees: HashMap::new(),
+        }
+    }
+}
+
+fn dedup(doc: &NodeRef, ms: Vec<Matched>, mode: Dedup) -> Vec<Matched> {
+    if mode == Dedup::Off {
+        return ms;
+    }
+
+    let mut seen = HashSet::new();
+    let mut ret = vec![];
+    for m in ms {
+        let key = (
+            m.bindings.clone(),
+            m.anchors.iter().map(node_id).collect::<Vec<_>>(),
+        );
+        if seen.insert(key) {
+            ret.push(m);
+        }
+    }
+
+    if mode == Dedup::NonOverlapping {
+        let order = doc
+            .inclusive_descendants()
+            .enumerate()
+            .map(|(i, r)| (node_id(&r), i))
+            .collect::<HashMap<_, _>>();
+        let pos = |m: &Matched| m.anchors.iter().map(|r| order[&node_id(r)]).min();
+        ret.sort_by_key(|m| pos(m));
+
+        let overlaps = |a: &Matched, b: &Matched| {
+            a.anchors.iter().any(|x| {
+                b.anchors.iter().any(|y| {
+                    x.inclusive_ancestors().any(|r| r == *y)
+                        || y.inclusive_ancestors().any(|r| r == *x)
+                })
+            })
+        };
+
+        let mut selected: Vec<Matched> = vec![];
+        for m in ret {
+            if !selected.iter().any(|r| overlaps(r, &m)) {
+                selected.push(m);
+            }
+        }
+        ret = selected;
+    }
+
+    ret
+}
+
+fn node_id(node: &NodeRef) -> *const kuchiki::Node {
+    node.deref()
+}
+
+fn match_text(
+    doc: &str,
+    pat: &str,
+    fold: Fold,
+    capture: &Capture,
+) -> Vec<BTreeMap<String, String>> {
+    if pat.find("{{").is_some() && pat.find("}}").is_some() {
+        // FIXME: cache regex
+        let mut re_str = String::new();
+
+        re_str += "^";
+
+        let mut vars = vec![];
+
+        let mut cur = pat;
+
+        while let Some(ix) = cur.find("{{") {
+            re_str += &regex::escape(&fold.apply(&cur[0..ix]));
+            cur = &cur[ix + 2..];
+            let close = cur.find("}}");
+            assert!(close.is_some(), "Invalid text pattern: \"{}\"", pat);
+            let close = close.unwrap();
+            vars.push(&cur[0..close]);
+            re_str += "(.*)";
+            cur = &cur[close + 2..];
+        }
+
+        re_str += &regex::escape(&fold.apply(cur));
+        re_str += "$";
+
+        let re = regex::Regex::new(&re_str).unwrap();
+
+        // Values are captured from the original text.
+        let (folded, offsets) = if fold.is_empty() {
+            (Cow::Borrowed(doc), None)
+        } else {
+            let (folded, offsets) = fold.apply_with_offsets(doc);
+            (Cow::Owned(folded), Some(offsets))
+        };
+        let pos = |i: usize| offsets.as_ref().map_or(i, |r| r[i]);
+
+        if let Some(caps) = re.captures(&folded) {
+            let mut ret = vec![BTreeMap::new()];
+            for (i, name) in vars.into_iter().enumerate() {
+                let m = caps.get(i + 1).unwrap();
+                let value = doc[pos(m.start())..pos(m.end())].to_string();
+                // A JSON path can select multiple values.
+                let values = match Variable::parse(name) {
+                    Some(var) => var.capture(&value, capture),
+                    None => vec![singleton(name.to_owned(), value)],
+                };
+                ret = product(&ret, &values);
+            }
+            ret
+        } else {
+            vec![]
+        }
+    } else {
+        if doc == pat || !fold.is_empty() && fold.apply(doc) == fold.apply(pat) {
+            vec![BTreeMap::new()]
+        } else {
+            vec![]
+        }
+    }
+}
+
+// Conversion of captured texts by `{{name:url}}` and filters.
+struct Capture<'a> {
+    filters: &'a Filters,
+    base: Option<&'a Url>,
+}
+
+impl<'a> Capture<'a> {
+    // Value of the variable for the captured text, or `None` if it can not be converted,
+    // e.g. by a malformed URL, or a filter rejects it. The candidate does not match then.
+    fn value(&self, var: &Variable, text: String) -> Option<String> {
+        let text = if var.srcset {
+            attr::largest_srcset(&text)?.to_owned()
+        } else {
+            text
+        };
+        let text = if var.url { self.resolve(&text)? } else { text };
+        self.filters.apply(&var.filters, text)
+    }
+
+    fn resolve(&self, text: &str) -> Option<String> {
+        let url = match self.base {
+            Some(base) => base.join(text),
+            None => Url::parse(text),
+        };
+        url.map(String::from).ok()
+    }
+}
+
+struct Variable {
+    name: String,
+    whole: bool,
+    // JSON path for `{{name:json:path}}`
+    json: Option<String>,
+    // `{{name:url}}`
+    url: bool,
+    // `{{name:srcset}}`, or `{{name:srcset:url}}` with `url`
+    srcset: bool,
+    // Filters for `{{name|filter}}`
+    filters: Vec<String>,
+}
+
+impl Variable {
+    // Parses the inside of `{{...}}`.
+    fn parse(s: &str) -> Option<Variable> {
+        let mut it = s.split('|');
+        let var = it.next()?;
+        let filters = it.map(|r| r.trim().to_owned()).collect();
+
+        let mut it = var.splitn(3, ':');
+        let var = it.next()?;
+
+        let mut ret = Variable {
+            name: var.to_owned(),
+            whole: false,
+            json: None,
+            url: false,
+            srcset: false,
+            filters,
+        };
+        match (it.next(), it.next()) {
+            (None, _) => {}
+            (Some("*"), None) => ret.whole = true,
+            (Some("json"), path) => ret.json = Some(path.unwrap_or("$").to_owned()),
+            (Some("url"), None) => ret.url = true,
+            (Some("srcset"), None) => ret.srcset = true,
+            (Some("srcset"), Some("url")) => {
+                ret.srcset = true;
+                ret.url = true;
+            }
+            _ => return None,
+        }
+        Some(ret)
+    }
+
+    // Values bound to the variable for the text.
+    fn capture(&self, text: &str, capture: &Capture) -> Vec<BTreeMap<String, String>> {
+        let values = match &self.json {
+            Some(path) => json::select(text, path),
+            None => vec![text.to_owned()],
+        };
+        values
+            .into_iter()
+            .filter_map(|v| capture.value(self, v))
+            .map(|v| singleton(self.name.clone(), v))
+            .collect()
+    }
+}
+
+fn is_var(s: &str) -> Option<Variable> {
+    let s = s.trim();
+    if s.starts_with("{{") && s.ends_with("}}") {
+        Variable::parse(&s[2..s.len() - 2])
+    } else {
+        None
+    }
+}
+
+// Compares names of elements by namespace and local name.
+// Pattern elements without namespace match any namespace.
+fn match_name(doc: &QualName, pat: &QualName) -> bool {
+    doc.local == pat.local && (pat.ns.is_empty() || doc.ns == pat.ns)
+}
+
+fn is_comment_like(node: &NodeRef) -> bool {
+    matches!(
+        node.data(),
+        NodeData::Comment(_) | NodeData::ProcessingInstruction(_)
+    )
+}
+
+// Comments in documents are ignored between siblings, unless the pattern is a comment.
+fn is_transparent(doc: &NodeRef, pattern: &PatternNode) -> bool {
+    is_comment_like(doc) && !pattern.is_comment_like()
+}
+
+fn is_skip(s: &str) -> bool {
+    s.trim() == "..."
+}
+
+fn is_special_attr(n: &ExpandedName) -> bool {
+    let s = n.local.as_ref();
+    s == "subseq" || s == "fold"
+}
+
+fn singleton(key: String, val: String) -> BTreeMap<String, String> {
+    let mut ret = BTreeMap::new();
+    ret.insert(key, val);
+    ret
+}
+
+fn match_attributes(
+    a1: &Attributes,
+    a2: &Attributes,
+    fold: Fold,
+    capture: &Capture,
+) -> Vec<BTreeMap<String, String>> {
+    let a1 = &a1.map;
+    let a2 = &a2.map;
+
+    let mut ret = vec![BTreeMap::new()];
+
+    for (k2, v2) in a2.iter() {
+        if is_special_attr(k2) {
+            continue;
+        }
+
+        let alts = if has_placeholder(&k2.local) {
+            // Name pattern, e.g. `data-{{key}}`, matches any of attributes
+            let mut alts = vec![];
+            for (k1, v1) in a1.iter().filter(|(k1, _)| k1.ns == k2.ns) {
+                let ms = match_text(&k1.local, &k2.local, Fold::default(), capture);
+                let vs = match_attribute_value(&k1.local, &v1.value, &v2.value, fold, capture);
+                alts.extend(product(&ms, &vs));
+            }
+            alts
+        } else if let Some(v1) = a1.get(k2) {
+            match_attribute_value(&k2.local, &v1.value, &v2.value, fold, capture)
+        } else {
+            vec![]
+        };
+
+        if alts.is_empty() {
+            return vec![];
+        }
+        ret = product(&ret, &alts);
+    }
+
+    ret
+}
+
+// All combinations of bindings of `a` and `b`.
+fn product(
+    a: &[BTreeMap<String, String>],
+    b: &[BTreeMap<String, String>],
+) -> Vec<BTreeMap<String, String>> {
+    a.iter()
+        .flat_map(|m| {
+            b.iter().map(move |v| {
+                let mut m = m.clone();
+                m.extend(v.clone());
+                m
+            })
+        })
+        .collect()
+}
+
+// Alternative bindings of attribute `name`. Returns no bindings if it does not match.
+fn match_attribute_value(
+    name: &str,
+    v1: &str,
+    v2: &str,
+    fold: Fold,
+    capture: &Capture,
+) -> Vec<BTreeMap<String, String>> {
+    if let Some(var) = is_var(v2) {
+        // Simple variable
+        var.capture(v1.trim(), capture)
+    } else if name == "style" {
+        match_style(v1, v2, fold, capture)
+    } else if has_placeholder(v2) {
+        // Complex pattern
+        match_text(v1, v2, fold, capture)
+    } else if is_subset(v1, v2, fold) {
+        // Set of attribute
+        vec![BTreeMap::new()]
+    } else {
+        vec![]
+    }
+}
+
+// Inline styles match when the pattern's declarations are in the document's ones.
+fn match_style(v1: &str, v2: &str, fold: Fold, capture: &Capture) -> Vec<BTreeMap<String, String>> {
+    let decls = attr::parse_style(v1);
+    let mut ret = vec![BTreeMap::new()];
+    for (name, pat) in attr::parse_style(v2) {
+        let doc = match decls.iter().rev().find(|(r, _)| *r == name) {
+            Some((_, doc)) => doc,
+            None => return vec![],
+        };
+        ret = product(&ret, &match_text(doc, &pat, fold, capture));
+    }
+    ret
+}
+
+fn has_placeholder(s: &str) -> bool {
+    let x = s.find("{{");
+    let y = s.find("}}");
+    x.is_some() && y.is_some() && x < y
+}
+
+fn is_subset(s1: &str, s2: &str, fold: Fold) -> bool {
+    if !fold.is_empty() {
+        return is_subset(&fold.apply(s1), &fold.apply(s2), Fold::default());
+    }
+
+    let ws1 = s1.split_whitespace().collect::<Vec<_>>();
+    for w in s2.split_whitespace() {
+        if !ws1.contains(&w) {
+            return false;
+        }
+    }
+    true
+}
+
+/// How to handle white-space in text nodes of documents
+///
+/// White-space only text nodes are removed in every mode,
+/// except in preserved elements such as `<pre>` (see [`MatchOptions::preserve_elements`]).
+/// Texts in preserved elements are always kept as they are.
+///
+/// ```
+/// use easy_scraper::{MatchOptions, Pattern, WhitespaceMode};
+///
+/// let pat = Pattern::new("<p>{{x}}</p>").unwrap();
+/// let doc = "<p>  Hello,\n    World  </p>";
+///
+/// assert_eq!(pat.matches(doc)[0]["x"], "Hello,\n    World");
+///
+/// let opts = MatchOptions {
+///     whitespace: WhitespaceMode::Collapse,
+///     ..MatchOptions::default()
+/// };
+/// assert_eq!(pat.matches_with_options(doc, &opts).unwrap()[0]["x"], "Hello, World");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WhitespaceMode {
+    /// Keep texts as they are.
+    Preserve,
+    /// Remove leading and trailing white-space.
+    #[default]
+    Trim,
+    /// Trim and replace runs of ASCII white-space with a single space.
+    Collapse,
+    /// Trim and replace runs of Unicode white-space (including `&nbsp;` and
+    /// ideographic space) with a single space, and remove zero-width characters.
+    Normalize,
+}
+
+fn normalize_whitespace(s: &str, mode: WhitespaceMode) -> String {
+    match mode {
+        WhitespaceMode::Preserve => s.to_owned(),
+        WhitespaceMode::Trim => s.trim().to_owned(),
+        WhitespaceMode::Collapse => s.split_ascii_whitespace().collect::<Vec<_>>().join(" "),
+        WhitespaceMode::Normalize => s
+            .chars()
+            .filter(|&c| !is_zero_width(c))
+            .collect::<String>()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+fn is_zero_width(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200b}' | '\u{200c}' | '\u{200d}' | '\u{2060}' | '\u{feff}'
+    )
+}
+
+fn default_preserve_elements() -> Vec<String> {
+    vec!["pre".to_owned(), "textarea".to_owned(), "code".to_owned()]
+}
+
+fn filter_whitespace(node: NodeRef) -> Option<NodeRef> {
+    Filter {
+        mode: WhitespaceMode::Trim,
+        preserve: &default_preserve_elements(),
+        comments: false,
+    }
+    .filter(node, false)
+}
+
+fn filter_pattern(node: NodeRef) -> Option<NodeRef> {
+    Filter {
+        mode: WhitespaceMode::Trim,
+        preserve: &[],
+        comments: true,
+    }
+    .filter(node, false)
+}
+
+// Copies the tree removing white-space and comments.
+struct Filter<'a> {
+    mode: WhitespaceMode,
+    // Elements whose texts are kept as they are
+    preserve: &'a [String],
+    // Keep comments and processing instructions
+    comments: bool,
+}
+
+impl<'a> Filter<'a> {
+    // * `preserved` - If true, `node` is in a preserved element.
+    fn filter(&self, node: NodeRef, preserved: bool) -> Option<NodeRef> {
+        match node.data() {
+            NodeData::Doctype(dt) => {
+                Some(NodeRef::new_doctype(&dt.name, &dt.public_id, &dt.system_id))
+            }
+            NodeData::Document(_) | NodeData::DocumentFragment => {
+                let ret = NodeRef::new_document();
+                self.filter_children(&node, &ret, preserved);
+                Some(ret)
+            }
+            NodeData::Element(element) => {
+                let ret = NodeRef::new_element(
+                    element.name.clone(),
+                    element.attributes.borrow().map.clone(),
+                );
+                let preserved =
+                    preserved || self.preserve.iter().any(|r| *r == *element.name.local);
+                self.filter_children(&node, &ret, preserved);
+                Some(ret)
+            }
+            NodeData::Text(text) => {
+                let text = text.borrow();
+
+                if preserved {
+                    return Some(NodeRef::new_text(text.to_owned()));
+                }
+
+                // White-space only nodes are removed even in `Preserve` mode
+                let check_mode = if self.mode == WhitespaceMode::Preserve {
+                    WhitespaceMode::Trim
+                } else {
+                    self.mode
+                };
+                if normalize_whitespace(&text, check_mode).is_empty() {
+                    None
+                } else {
+                    Some(NodeRef::new_text(normalize_whitespace(&text, self.mode)))
+                }
+            }
+            NodeData::Comment(text) if self.comments => {
+                Some(NodeRef::new_comment(text.borrow().clone()))
+            }
+            NodeData::ProcessingInstruction(pi) if self.comments => {
+                let pi = pi.borrow();
+                Some(NodeRef::new_processing_instruction(&pi.0, &pi.1))
+            }
+            NodeData::Comment(_) | NodeData::ProcessingInstruction(_) => None,
+        }
+    }
+
+    fn filter_children(&self, node: &NodeRef, ret: &NodeRef, preserved: bool) {
+        for child in node.children() {
+            if let Some(child) = self.filter(child, preserved) {
+                ret.append(child);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_basic() {
+    let doc = r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+    </head>
+    <body>
+        <ul>
+            <li>1</li>
+            <li>2</li>
+            <li>3</li>
+        </ul>
+    </body>
+</html>
+"#;
+
+    let pat = Pattern::new(
+        r#"
+<ul>
+    <li>{{hoge}}</li>
+</ul>
+"#,
+    )
+    .unwrap();
+
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 3);
+    assert_eq!(ms[0]["hoge"], "1");
+    assert_eq!(ms[1]["hoge"], "2");
+    assert_eq!(ms[2]["hoge"], "3");
+
+    let pat = Pattern::new(
+        r#"
+<ul>
+    <li>{{hoge}}</li>
+    <li>{{moge}}</li>
+</ul>
+"#,
+    )
+    .unwrap();
+
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["hoge"], "1");
+    assert_eq!(ms[0]["moge"], "2");
+    assert_eq!(ms[1]["hoge"], "2");
+    assert_eq!(ms[1]["moge"], "3");
+}
+
+#[test]
+fn test_attribute() {
+    let doc = r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+    </head>
+    <body>
+        <div class="foo bar baz">
+            hello
+        </div>
+    </body>
+</html>
+"#;
+
+    let pat = Pattern::new(r#"<div>{{foo}}</div>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["foo"], "hello");
+
+    let pat = Pattern::new(r#"<div class="">{{foo}}</div>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["foo"], "hello");
+
+    let pat = Pattern::new(r#"<div class="foo">{{foo}}</div>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["foo"], "hello");
+
+    let pat = Pattern::new(r#"<div class="foo bar">{{foo}}</div>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["foo"], "hello");
+
+    let pat = Pattern::new(r#"<div class="foo bar baz">{{foo}}</div>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["foo"], "hello");
+
+    let pat = Pattern::new(r#"<div class="baz foo bar">{{foo}}</div>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["foo"], "hello");
+
+    let pat = Pattern::new(r#"<div class="hoge">{{foo}}</div>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 0);
+
+    let pat = Pattern::new(r#"<div id="">{{foo}}</div>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 0);
+}
+
+#[test]
+fn test_attribute_pattern() {
+    let doc = r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+    </head>
+    <body>
+        <a href="https://www.google.com">Google</a>
+        <p>
+            <a href="https://github.com">GitHub</a>
+        </p>
+    </body>
+</html>
+"#;
+
+    let pat = Pattern::new(r#"<a href="{{url}}">{{link}}</a>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["url"], "https://www.google.com");
+    assert_eq!(ms[0]["link"], "Google");
+    assert_eq!(ms[1]["url"], "https://github.com");
+    assert_eq!(ms[1]["link"], "GitHub");
+}
+
+#[test]
+fn test_skip() {
+    let doc = r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+    </head>
+    <body>
+        <ul>
+            <li>1</li>
+            <li>2</li>
+            <li>3</li>
+        </ul>
+    </body>
+</html>
+"#;
+
+    let pat = Pattern::new(
+        r#"
+<ul>
+    <li>{{hoge}}</li>
+    ...
+    <li>{{moge}}</li>
+</ul>
+"#,
+    )
+    .unwrap();
+
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 3);
+    assert_eq!(ms[0]["hoge"], "1");
+    assert_eq!(ms[0]["moge"], "2");
+    assert_eq!(ms[1]["hoge"], "1");
+    assert_eq!(ms[1]["moge"], "3");
+    assert_eq!(ms[2]["hoge"], "2");
+    assert_eq!(ms[2]["moge"], "3");
+}
+
+#[test]
+fn test_all_match() {
+    let doc = r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+    </head>
+    <body>
+        Hello
+        <span>hoge</span>
+        World
+    </body>
+</html>
+"#;
+
+    let pat = Pattern::new(r#"<body>{{body:*}}</body>"#).unwrap();
+
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["body"], "Hello<span>hoge</span>World");
+}
+
+#[test]
+fn test_partial() {
+    let doc = r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+    </head>
+    <body>
+        <ul>
+            <li>Test 1, 2</li>
+            <li>Test 3, 4</li>
+            <li>Test 5, 6</li>
+        </ul>
+    </body>
+</html>
+"#;
+
+    let pat = Pattern::new(r#"<ul>Test {{foo}}, {{bar}}</ul>"#).unwrap();
+
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 3);
+    assert_eq!(ms[0]["foo"], "1");
+    assert_eq!(ms[0]["bar"], "2");
+    assert_eq!(ms[1]["foo"], "3");
+    assert_eq!(ms[1]["bar"], "4");
+    assert_eq!(ms[2]["foo"], "5");
+    assert_eq!(ms[2]["bar"], "6");
+}
+
+#[test]
+fn test_attr_partial() {
+    let doc = r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+    </head>
+    <body>
+        <a href="/users/foo/info"></a>
+        <a href="/users/bar/info"></a>
+        <a href="/users/baz/info"></a>
+    </body>
+</html>
+"#;
+
+    let pat = Pattern::new(r#"<a href="/users/{{user}}/info"></a>"#).unwrap();
+
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 3);
+    assert_eq!(ms[0]["user"], "foo");
+    assert_eq!(ms[1]["user"], "bar");
+    assert_eq!(ms[2]["user"], "baz");
+}
+
+#[test]
+fn test_partial_escape() {
+    let doc = r#"
+<ul>
+    <li>Price (USD): $100.</li>
+    <li>Price USD: 200x</li>
+</ul>
+"#;
+
+    let pat = Pattern::new(r#"<li>Price (USD): ${{price}}.</li>"#).unwrap();
+
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["price"], "100");
+}
+
+#[test]
+fn test_table_skip() {
+    let doc = r#"
+<!DOCTYPE html>
+<html lang="en">
+    <head>
+    </head>
+    <body>
+        <table>
+            <tr><th>AAA</th><td>aaa</td></tr>
+            <tr><th>BBB</th><td>bbb</td></tr>
+            <tr><th>CCC</th><td>ccc</td></tr>
+            <tr><th>DDD</th><td>ddd</td></tr>
+            <tr><th>EEE</th><td>eee</td></tr>
+        </table>
+    </body>
+</html>
+"#;
+
+    let pat = Pattern::new(
+        r#"
+<table subseq>
+    <tr><th>AAA</th><td>{{a}}</td></tr>
+    <tr><th>BBB</th><td>{{b}}</td></tr>
+    <tr><th>DDD</th><td>{{d}}</td></tr>
+</table>
+"#,
+    )
+    .unwrap();
+
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["a"], "aaa");
+    assert_eq!(ms[0]["b"], "bbb");
+    assert_eq!(ms[0]["d"], "ddd");
+}
+
+#[test]
+fn test_limits() {
+    let doc = format!(
+        "<ul>{}</ul>",
+        (0..100)
+            .map(|i| format!("<li>{}</li>", i))
+            .collect::<String>()
+    );
+
+    let pat = Pattern::new(
+        r#"
+<ul>
+    <li>{{a}}</li>
+    ...
+    <li>{{b}}</li>
+</ul>
+"#,
+    )
+    .unwrap();
+
+    let opts = MatchOptions {
+        max_results: Some(10),
+        ..MatchOptions::default()
+    };
+    let ms = pat.matches_with_options(&doc, &opts).unwrap();
+    assert_eq!(ms.len(), 10);
+    assert_eq!(ms, pat.matches(&doc)[..10].to_vec());
+
+    let opts = MatchOptions {
+        max_steps: Some(100),
+        ..MatchOptions::default()
+    };
+    assert_eq!(
+        pat.matches_with_options(&doc, &opts),
+        Err(MatchError::TooManySteps)
+    );
+
+    let opts = MatchOptions {
+        max_depth: Some(5),
+        ..MatchOptions::default()
+    };
+    assert_eq!(
+        pat.matches_with_options(&doc, &opts),
+        Err(MatchError::TooDeep)
+    );
+
+    let opts = MatchOptions {
+        deadline: Some(Instant::now()),
+        ..MatchOptions::default()
+    };
+    assert_eq!(
+        pat.matches_with_options(&doc, &opts),
+        Err(MatchError::Timeout)
+    );
+}
+
+#[test]
+fn test_memo() {
+    // Without memoization, these take time exponential in the number of rows.
+    let doc = format!("<table>{}</table>", "<tr><td>x</td></tr>".repeat(40));
+    let opts = MatchOptions {
+        max_steps: Some(100_000),
+        ..MatchOptions::default()
+    };
+
+    let rows = "<tr><td>x</td></tr>".repeat(10);
+    let pat = Pattern::new(&format!(
+        "<table subseq>{}<tr><td>y</td></tr></table>",
+        rows
+    ))
+    .unwrap();
+    assert_eq!(pat.matches_with_options(&doc, &opts), Ok(vec![]));
+
+    let doc = format!("<ul>{}</ul>", "<li>x</li>".repeat(40));
+    let items = ["<li>x</li>"; 10].join("...");
+    let pat = Pattern::new(&format!("<ul>{}...<li>y</li></ul>", items)).unwrap();
+    assert_eq!(pat.matches_with_options(&doc, &opts), Ok(vec![]));
+
+    // 40 choose 2 pairs
+    let pat = Pattern::new("<ul subseq><li>{{a}}</li><li>{{b}}</li></ul>").unwrap();
+    assert_eq!(pat.matches_with_options(&doc, &opts).unwrap().len(), 780);
+}
+
+#[test]
+fn test_dedup() {
+    let doc = r#"
+<div>
+    <div>
+        <span><span>a</span></span>
+    </div>
+    <div>
+        <span>b</span>
+    </div>
+</div>
+"#;
+
+    let pat = Pattern::new(r#"<div><span>{{x}}</span></div>"#).unwrap();
+
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 6);
+
+    let opts = MatchOptions {
+        dedup: Dedup::Identical,
+        ..MatchOptions::default()
+    };
+    let ms = pat.matches_with_options(doc, &opts).unwrap();
+    assert_eq!(ms.len(), 4);
+    // duplicates are removed before `max_results`
+    let opts = MatchOptions {
+        max_results: Some(3),
+        dedup: Dedup::Identical,
+        ..MatchOptions::default()
+    };
+    let ms = pat.matches_with_options(doc, &opts).unwrap();
+    assert_eq!(ms.len(), 3);
+
+    let opts = MatchOptions {
+        dedup: Dedup::NonOverlapping,
+        ..MatchOptions::default()
+    };
+    let ms = pat.matches_with_options(doc, &opts).unwrap();
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["x"], "a");
+}
+
+#[test]
+fn test_whitespace() {
+    let doc = r#"
+<!DOCTYPE html>
+<html lang="en">
+    <body>
+        <p>
+            Hello,
+            World
+        </p>
+        <pre>  fn main() {
+      <b>println!</b>("Hello");
+  }</pre>
+    </body>
+</html>
+"#;
+
+    let pat = Pattern::new(r#"<pre>{{code:*}}</pre>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(
+        ms[0]["code"],
+        "  fn main() {\n      <b>println!</b>(\"Hello\");\n  }"
+    );
+
+    let pat = Pattern::new(r#"<p>Hello, World</p>"#).unwrap();
+    assert_eq!(pat.matches(doc).len(), 0);
+
+    let opts = MatchOptions {
+        whitespace: WhitespaceMode::Collapse,
+        ..MatchOptions::default()
+    };
+    assert_eq!(pat.matches_with_options(doc, &opts).unwrap().len(), 1);
+
+    let pat = Pattern::new(r#"<p>{{x}}</p>"#).unwrap();
+    let opts = MatchOptions {
+        whitespace: WhitespaceMode::Preserve,
+        ..MatchOptions::default()
+    };
+    let ms = pat.matches_with_options(doc, &opts).unwrap();
+    assert_eq!(
+        ms[0]["x"],
+        "\n            Hello,\n            World\n        "
+    );
+
+    let doc = "<p>\u{3000}Hello,\u{a0}\u{200b}World</p>";
+    let opts = MatchOptions {
+        whitespace: WhitespaceMode::Normalize,
+        ..MatchOptions::default()
+    };
+    let ms = pat.matches_with_options(doc, &opts).unwrap();
+    assert_eq!(ms[0]["x"], "Hello, World");
+}
+
+#[test]
+fn test_filter() {
+    let doc = r#"
+<ul>
+    <li><a href=" /a ">  Foo   Bar </a><span>¥1,234</span></li>
+    <li><a href="/b">BAZ</a><span>sold out</span></li>
+    <li><a href="/c">Tom &amp;amp; Jerry</a><span>Price: $5</span></li>
+</ul>
+<div data-props='{"price": "1,980円"}'><b>x</b> y</div>
+"#;
+
+    let pat = Pattern::new(
+        r#"<li><a href="{{url|trim}}">{{title|collapse|lower}}</a><span>{{price|number}}</span></li>"#,
+    )
+    .unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["url"], "/a");
+    assert_eq!(ms[0]["title"], "foo bar");
+    assert_eq!(ms[0]["price"], "1234");
+    assert_eq!(ms[1]["title"], "tom &amp; jerry");
+    assert_eq!(ms[1]["price"], "5");
+
+    let pat =
+        Pattern::new("<li><a>{{title|unescape}}</a><span>Price: {{price|number}}</span></li>")
+            .unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["title"], "Tom & Jerry");
+
+    let pat = Pattern::new(r#"<div data-props="{{p:json:$.price|number}}">{{html:*|upper}}</div>"#)
+        .unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms[0]["p"], "1980");
+    assert_eq!(ms[0]["html"], "<B>X</B>Y");
+
+    let pat = PatternBuilder::new()
+        .filter("path", |s| s.trim().strip_prefix('/').map(|r| r.to_owned()))
+        .filter("trim", |s| Some(s.trim_matches(' ').to_owned()))
+        .build(r#"<a href="{{url|path|upper}}"></a>"#)
+        .unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(
+        ms.iter().map(|m| m["url"].as_str()).collect::<Vec<_>>(),
+        vec!["A", "B", "C"]
+    );
+
+    assert!(Pattern::new("<p>{{a|nothing}}</p>").is_err());
+    assert!(Pattern::new(r#"<p class="x-{{a|nothing}}"></p>"#).is_err());
+}
+
+#[test]
+fn test_url() {
+    let doc = r#"
+<html>
+<head><base href="/news/"></head>
+<body>
+    <a href="item?id=1">One</a>
+    <a href=" https://other.example.org/x ">Two</a>
+    <a href="../about#top">Three</a>
+</body>
+</html>
+"#;
+
+    let pat = Pattern::new(r#"<a href="{{url:url}}">{{title}}</a>"#).unwrap();
+    let ms = pat
+        .matches_with_base(doc, "https://example.com/top")
+        .unwrap();
+    assert_eq!(ms.len(), 3);
+    assert_eq!(ms[0]["url"], "https://example.com/news/item?id=1");
+    assert_eq!(ms[1]["url"], "https://other.example.org/x");
+    assert_eq!(ms[2]["url"], "https://example.com/about#top");
+
+    // relative URLs without base, and malformed URLs do not match
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["title"], "Two");
+    let ms = pat
+        .matches(r#"<li><a href="https://example.com/a">A</a></li><li><a href="/rel">B</a></li>"#);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["url"], "https://example.com/a");
+    let ms = pat
+        .matches_with_base("<a href='http://[::1'>x</a>", "https://example.com/")
+        .unwrap();
+    assert!(ms.is_empty());
+    assert!(matches!(
+        pat.matches_with_base(doc, "example.com"),
+        Err(MatchError::Capture(_))
+    ));
+
+    // absolute `<base href>`, with filters
+    let pat = Pattern::new(r#"<a href="{{url:url|upper}}">One</a>"#).unwrap();
+    let doc = r#"<base href="https://example.com/a/"><a href="b">One</a>"#;
+    assert_eq!(pat.matches(doc)[0]["url"], "HTTPS://EXAMPLE.COM/A/B");
+
+    // streaming
+    let opts = MatchOptions {
+        base_url: Some("https://example.org/".to_owned()),
+        ..MatchOptions::default()
+    };
+    let ms = pat
+        .matches_reader_with_options(doc.as_bytes(), &opts)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(ms[0]["url"], "HTTPS://EXAMPLE.COM/A/B");
+}
+
+#[test]
+fn test_compound_attribute() {
+    let doc = r#"
+<div class="card" data-id="12" data-kind="book" style="color: red; Background-Image: url('/img/a.jpg')">
+    <img src="s.jpg" srcset="s.jpg 320w, l.jpg 1280w, m.jpg 640w">
+</div>
+<div class="card" style="color: blue">
+    <img data-src="lazy.jpg" srcset="x1.jpg, x2.jpg 2x">
+</div>
+"#;
+
+    let pat = Pattern::new(r#"<img srcset="{{src:srcset}}">"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms[0]["src"], "l.jpg");
+    assert_eq!(ms[1]["src"], "x2.jpg");
+
+    let pat = Pattern::new(r#"<img srcset="{{src:srcset:url}}">"#).unwrap();
+    let ms = pat.matches_with_base(doc, "https://example.com/").unwrap();
+    assert_eq!(ms[0]["src"], "https://example.com/l.jpg");
+
+    let pat = Pattern::new(r#"<div style="background-image: url({{bg}})"></div>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["bg"], "/img/a.jpg");
+
+    let pat = Pattern::new(r#"<div class="card" style="color: {{color}}"></div>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[1]["color"], "blue");
+
+    let pat = Pattern::new(r#"<div style="color:red"></div>"#).unwrap();
+    assert_eq!(pat.matches(doc).len(), 1);
+
+    let pat =
+        Pattern::new(r#"<i style="background-image: url({{bg}}); color: {{c}}"></i>"#).unwrap();
+    let ms = pat.matches(
+        r#"<i style='background-image: url("data:image/png;base64,AAA="); color: red'></i>"#,
+    );
+    assert_eq!(ms[0]["bg"], "data:image/png;base64,AAA=");
+    assert_eq!(ms[0]["c"], "red");
+
+    let pat = Pattern::new(r#"<div class="card" data-{{key}}="{{value}}"></div>"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["key"], "id");
+    assert_eq!(ms[0]["value"], "12");
+    assert_eq!(ms[1]["key"], "kind");
+    assert_eq!(ms[1]["value"], "book");
+
+    let pat = Pattern::new(r#"<img data-{{key}}="{{value}}" srcset="{{src:srcset}}">"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["key"], "src");
+    assert_eq!(ms[0]["value"], "lazy.jpg");
+}
+
+#[test]
+fn test_fold_options() {
+    let doc = r#"
+<ul class="Items">
+    <li class="Item NEW"><b>ＰＲＩＣＥ： １，２００円</b><a title="café">x</a></li>
+    <li class="item"><b>price: 300円</b><a title="cafe">y</a></li>
+</ul>
+"#;
+
+    let pat = Pattern::new(r#"<li class="item"><b>price: {{p}}円</b></li>"#).unwrap();
+    assert_eq!(pat.matches(doc).len(), 1);
+
+    let pat = PatternBuilder::new()
+        .case_insensitive(true)
+        .nfkc(true)
+        .build(r#"<li class="item"><b>price: {{p|number}}円</b></li>"#)
+        .unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["p"], "1200");
+    assert_eq!(ms[1]["p"], "300");
+
+    // captured from the original text
+    let pat = PatternBuilder::new()
+        .nfkc(true)
+        .build(r#"<b>ＰＲＩＣＥ: {{p}}</b>"#)
+        .unwrap();
+    assert_eq!(pat.matches(doc)[0]["p"], "１，２００円");
+
+    let pat = PatternBuilder::new()
+        .ignore_diacritics(true)
+        .build(r#"<a title="cafe">{{x}}</a>"#)
+        .unwrap();
+    assert_eq!(pat.matches(doc).len(), 2);
+
+    // per node
+    let pat = Pattern::new(r#"<ul class="items"><li class="new"><a>{{x}}</a></li></ul>"#).unwrap();
+    assert_eq!(pat.matches(doc).len(), 0);
+    let pat =
+        Pattern::new(r#"<ul fold="case" class="items"><li class="new"><a>{{x}}</a></li></ul>"#)
+            .unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["x"], "x");
+
+    assert!(Pattern::new(r#"<p fold="unknown">{{x}}</p>"#).is_err());
+}
+
+#[test]
+fn test_fuzzy() {
+    let doc = r#"
+<table>
+    <tr><th>Release Date:</th><td>2020-01-01</td></tr>
+    <tr><th>Price</th><td>100</td></tr>
+    <tr><th>Released</th><td>2019-12-31</td></tr>
+</table>
+"#;
+
+    let src = r#"<table subseq><tr><th>Release date</th><td>{{date}}</td></tr><tr><th>Price</th><td>{{price}}</td></tr></table>"#;
+    assert!(Pattern::new(src).unwrap().matches(doc).is_empty());
+
+    let pat = PatternBuilder::new().fuzzy(2, 0.8).build(src).unwrap();
+    let ms = pat.matches_scored(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0].bindings["date"], "2020-01-01");
+    assert_eq!(ms[0].bindings["price"], "100");
+    assert!(ms[0].score < 1.0 && ms[0].score > 0.5);
+
+    // exact matches have score 1
+    let pat = PatternBuilder::new()
+        .fuzzy(2, 0.8)
+        .build(r#"<tr><th>Price</th><td>{{price}}</td></tr>"#)
+        .unwrap();
+    let ms = pat.matches_scored(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0].score, 1.0);
+
+    // texts with placeholders are not matched fuzzily
+    let pat = PatternBuilder::new()
+        .fuzzy(2, 0.8)
+        .build(r#"<th>Price: {{x}}</th>"#)
+        .unwrap();
+    assert!(pat.matches(doc).is_empty());
+
+    // folded before comparison
+    let pat = PatternBuilder::new()
+        .fuzzy(0, 1.0)
+        .case_insensitive(true)
+        .build(r#"<tr><th>release date</th><td>{{date}}</td></tr>"#)
+        .unwrap();
+    assert_eq!(pat.matches(doc)[0]["date"], "2020-01-01");
+
+    // similarity 0 would match any text
+    assert!(PatternBuilder::new().fuzzy(2, 0.0).build(src).is_err());
+    assert!(PatternBuilder::new().fuzzy(2, 1.5).build(src).is_err());
+}
+
+#[test]
+fn test_best_matches() {
+    let doc = r#"
+<table>
+    <tr><th>AAA</th><td>aaa</td></tr>
+    <tr><th>BBB</th><td>bbb</td></tr>
+    <tr><th>DDD</th><td class="x">ddd</td></tr>
+</table>
+<p>no table</p>
+"#;
+
+    let pat = Pattern::new(
+        r#"
+<table subseq>
+    <tr><th>AAA</th><td>{{a}}</td></tr>
+    <tr><th>CCC</th><td>{{c}}</td></tr>
+    <tr><th>DDD</th><td class="y">{{d}}</td></tr>
+</table>
+"#,
+    )
+    .unwrap();
+    assert!(pat.matches(doc).is_empty());
+
+    let ms = pat.best_matches(doc, 3);
+    assert_eq!(ms[0].bindings["a"], "aaa");
+    assert_eq!(ms[0].bindings["d"], "ddd");
+    // the row of BBB matches except for the label
+    assert_eq!(ms[0].bindings["c"], "bbb");
+    assert_eq!(
+        ms[0].unmatched,
+        vec![
+            "table > tr:nth-of-type(2) > th > \"CCC\"",
+            "table > tr:nth-of-type(3) > td[class]"
+        ]
+    );
+    assert!(ms[0].score > 0.5 && ms[0].score < 1.0);
+
+    // exact matches have score 1
+    let pat = Pattern::new(r#"<tr><th>BBB</th><td>{{b}}</td></tr>"#).unwrap();
+    let ms = pat.best_matches(doc, 2);
+    assert_eq!(ms[0].score, 1.0);
+    assert_eq!(ms[0].bindings["b"], "bbb");
+    assert!(ms[0].unmatched.is_empty());
+    assert!(ms[1].score < 1.0);
+    assert_eq!(ms[1].unmatched, vec!["tr > th > \"BBB\""]);
+
+    // multiple roots
+    let pat = Pattern::new(r#"<th>CCC</th><td>{{x}}</td>"#).unwrap();
+    let ms = pat.best_matches(doc, 1);
+    assert_eq!(ms[0].bindings["x"], "aaa");
+    assert_eq!(ms[0].unmatched, vec!["th > \"CCC\""]);
+
+    assert!(Pattern::new("<ul><li>{{x}}</li></ul>")
+        .unwrap()
+        .best_matches(doc, 1)
+        .is_empty());
+}
+
+#[test]
+fn test_library() {
+    let mut lib = PatternLibrary::new(
+        r#"<template name="price"><span class="price">{{amount|number}}</span></template>"#,
+    )
+    .unwrap();
+    lib.add(
+        r#"
+<template name="card">
+    <div class="card">
+        <h2>{{title}}</h2>
+        <use ref="price"/>
+    </div>
+</template>
+"#,
+    )
+    .unwrap();
+
+    let doc = r#"
+<div class="card"><h2>Foo</h2><span class="price">$1,000</span></div>
+<div class="card"><h2>Bar</h2><span class="price">$20</span></div>
+"#;
+    let pat = lib.build(r#"<use ref="card" as="item">"#).unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["item.title"], "Foo");
+    assert_eq!(ms[0]["item.price.amount"], "1000");
+
+    // the same as the expanded pattern
+    let pat = Pattern::new(
+        r#"<div class="card"><h2>{{title}}</h2><span class="price">{{price.amount|number}}</span></div>"#,
+    )
+    .unwrap();
+    assert_eq!(pat.matches(doc)[1]["price.amount"], "20");
+
+    // with options of the builder
+    let pat = PatternBuilder::new()
+        .case_insensitive(true)
+        .library(&lib)
+        .build(r#"<body><h2>FOO</h2><use ref="price" as="p"/></body>"#)
+        .unwrap();
+    assert_eq!(pat.matches(doc)[0]["p.amount"], "1000");
+
+    assert!(lib.build(r#"<div><use ref="unknown"/></div>"#).is_err());
+    assert!(Pattern::new(r#"<div><use ref="price"></use></div>"#)
+        .unwrap()
+        .matches(doc)
+        .is_empty());
+}
+
+#[test]
+fn test_compose() {
+    let debug = |p: &Pattern| format!("{:?}", p.pattern);
+
+    let nodes = Node::element("UL")
+        .subseq()
+        .child(
+            Node::element("li")
+                .attr("Class", "row {{cls}}")
+                .child(Node::element("b").child(Node::text("  AAA ")))
+                .child(Node::element("i").child(Node::placeholder("a"))),
+        )
+        .child(Node::skip())
+        .child(Node::element("li").child(Node::placeholder("b:*")));
+    let src = r#"
+<ul subseq>
+    <li class="row {{cls}}"><b>AAA</b><i>{{a}}</i></li>
+    ...
+    <li>{{b:*}}</li>
+</ul>
+"#;
+    assert_eq!(
+        debug(&Pattern::from_nodes(&[nodes]).unwrap()),
+        debug(&Pattern::new(src).unwrap())
+    );
+
+    // names and namespaces in SVG and MathML
+    let nodes = Node::element("div")
+        .child(
+            Node::element("svg")
+                .attr("viewBox", "{{v}}")
+                .child(Node::element("clipPath").attr("xlink:href", "#a"))
+                .child(Node::element("foreignObject").child(Node::element("p"))),
+        )
+        .child(Node::element("math").attr("definitionURL", "x"));
+    let src = r##"<div><svg viewBox="{{v}}"><clippath xlink:href="#a"></clippath><foreignobject><p></p></foreignobject></svg><math definitionurl="x"></math></div>"##;
+    assert_eq!(
+        debug(&Pattern::from_nodes(&[nodes]).unwrap()),
+        debug(&Pattern::new(src).unwrap())
+    );
+    let pat = Pattern::from_nodes(&[Node::element("svg").attr("viewBox", "{{v}}")]).unwrap();
+    assert_eq!(
+        pat.matches(r#"<svg viewBox="0 0 10 10"></svg>"#)[0]["v"],
+        "0 0 10 10"
+    );
+
+    // texts are not escaped
+    let pat = Pattern::from_nodes(&[Node::element("p").child(Node::text("a < b & c"))]).unwrap();
+    assert_eq!(pat.matches("<p>a &lt; b &amp; c</p>").len(), 1);
+    assert!(Pattern::from_nodes(&[Node::placeholder("x|unknown")]).is_err());
+
+    let doc = r#"
+<div class="card"><h2>Foo</h2><p><span class="price">100</span></p></div>
+<div class="card"><h2>Bar</h2><b class="cost">200</b></div>
+"#;
+    let h2 = Pattern::new("<h2>{{title}}</h2>").unwrap();
+    let price = Pattern::new(r#"<p><span class="price">{{price}}</span></p>"#).unwrap();
+    let cost = Pattern::new(r#"<b class="cost">{{price}}</b>"#).unwrap();
+    let card = Pattern::new(r#"<div class="card"></div>"#).unwrap();
+
+    let pat = h2.then(&price).unwrap();
+    assert_eq!(
+        debug(&pat),
+        debug(
+            &Pattern::new(r#"<h2>{{title}}</h2><p><span class="price">{{price}}</span></p>"#)
+                .unwrap()
+        )
+    );
+    assert_eq!(pat.matches(doc).len(), 1);
+
+    // `then` and `inside` are distributed over alternatives
+    let pat = h2
+        .then(&price.or(&cost).unwrap())
+        .unwrap()
+        .inside(&card)
+        .unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["title"], "Foo");
+    assert_eq!(ms[0]["price"], "100");
+    assert_eq!(ms[1]["title"], "Bar");
+    assert_eq!(ms[1]["price"], "200");
+    let ms = pat.best_matches(doc, 5);
+    assert!(ms[0].score == 1.0 && ms[1].score == 1.0);
+    assert!(ms[2..].iter().all(|r| r.score < 1.0));
+    assert!(pat.matches_reader(doc.as_bytes()).is_err());
+
+    let opts = MatchOptions {
+        max_results: Some(1),
+        ..MatchOptions::default()
+    };
+    assert_eq!(pat.matches_with_options(doc, &opts).unwrap().len(), 1);
+
+    // adjacent texts are merged as in parsed patterns
+    let pat = Pattern::from_nodes(&[Node::element("li")
+        .child(Node::text("Price: "))
+        .child(Node::placeholder("p"))])
+    .unwrap();
+    assert_eq!(pat.matches("<li>Price: 100</li>")[0]["p"], "100");
+
+    // custom filters of both patterns are kept
+    let yen = PatternBuilder::new()
+        .filter("double", |s| Some(s.repeat(2)))
+        .build("<b>{{x|double}}</b>")
+        .unwrap();
+    let pat = h2.then(&yen).unwrap();
+    assert_eq!(pat.matches("<h2>A</h2><b>1</b>")[0]["x"], "11");
+    // but not different filters of the same name
+    let half = PatternBuilder::new()
+        .filter("double", |s| Some(s[..s.len() / 2].to_owned()))
+        .build("<i>{{y|double}}</i>")
+        .unwrap();
+    assert!(yen.then(&half).is_err());
+    assert!(yen.then(&yen).is_ok());
+
+    assert!(h2.inside(&h2.then(&price).unwrap()).is_err());
+    assert!(h2.then(&Pattern::new_xml("<a>{{x}}</a>").unwrap()).is_err());
+    assert!(h2
+        .then(&Pattern::new("<html><body></body></html>").unwrap())
+        .is_err());
+}
+
+#[test]
+fn test_display() {
+    let round_trip = |pat: &Pattern| {
+        let src = pat.to_string();
+        let parsed = match pat.syntax {
+            Syntax::Html => Pattern::new(&src),
+            Syntax::Xml => Pattern::new_xml(&src),
         }
-    } else if let Some(_) = node.as_comment() {
-        assert!(node.first_child().is_none());
-        None
-    } else {
-        unreachable!()
-    }
+        .unwrap();
+        assert_eq!(parsed.to_string(), src);
+        assert_eq!(
+            format!("{:?}", parsed.pattern),
+            format!("{:?}", pat.pattern)
+        );
+        src
+    };
+
+    let pat = Pattern::new(
+        r#"
+<table>
+    <tr><th>Price &amp; tax</th><td class='a "b"'>{{price|number}}</td></tr>
+</table>"#,
+    )
+    .unwrap();
+    assert_eq!(
+        round_trip(&pat),
+        r#"<table>
+    <tr>
+        <th>Price &amp; tax</th>
+        <td class="a &quot;b&quot;">{{price|number}}</td>
+    </tr>
+</table>
+"#
+    );
+
+    let pat =
+        Pattern::new(r#"<ul subseq><li>{{a}}</li> ... <li><img src="{{src:url}}"><br></li></ul>"#)
+            .unwrap();
+    assert_eq!(
+        round_trip(&pat),
+        r#"<ul subseq>
+    <li>{{a}}</li>
+    ...
+    <li>
+        <img src="{{src:url}}">
+        <br>
+    </li>
+</ul>
+"#
+    );
+
+    let pat =
+        Pattern::new(r#"<div fold="case"><!-- id:{{id}} --><p>{{body:*}}</p></div>"#).unwrap();
+    round_trip(&pat);
+    let debug = format!("{:?}", pat);
+    assert!(debug.starts_with("Pattern (Html fragment)\n"));
+    assert!(debug.contains("Element <div> (fold: case)"));
+    assert!(debug.contains("Comment \" id:{{id}} \""));
+    assert!(debug.contains("Subtree {{body:*}}"));
+
+    // implied elements are printed
+    let pat = Pattern::new("<!DOCTYPE html><p>{{x}}</p>").unwrap();
+    assert!(round_trip(&pat).contains("<head></head>"));
+
+    let pat = Pattern::new_xml(
+        r#"<item xmlns:f="http://example.com/f"><f:title>{{t}}</f:title><dc:creator>{{c}}</dc:creator></item>"#,
+    )
+    .unwrap();
+    assert_eq!(
+        round_trip(&pat),
+        r#"<item>
+    <f:title xmlns:f="http://example.com/f">{{t}}</f:title>
+    <dc:creator>{{c}}</dc:creator>
+</item>
+"#
+    );
+
+    let pat = Pattern::new("<b>{{x}}</b>")
+        .unwrap()
+        .or(&Pattern::new("<i>{{x}}</i>").unwrap())
+        .unwrap();
+    assert_eq!(pat.to_string(), "<b>{{x}}</b>\n\n<i>{{x}}</i>\n");
+    assert!(format!("{:?}", pat).contains("\nOr\n"));
+    // each alternative is parsed separately
+    let srcs = std::iter::once(&pat)
+        .chain(pat.alternatives.iter())
+        .map(|alt| {
+            round_trip(&Pattern {
+                alternatives: vec![],
+                ..alt.clone()
+            })
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(srcs.join("\n"), pat.to_string());
+
+    // patterns built from nodes
+    let pat = Pattern::from_nodes(&[Node::element("ul").subseq().child(
+        Node::element("li")
+            .child(Node::text("Price: "))
+            .child(Node::placeholder("p"))
+            .child(Node::element("svg").child(Node::element("clippath"))),
+    )])
+    .unwrap();
+    assert_eq!(
+        round_trip(&pat),
+        r#"<ul subseq>
+    <li>
+        Price: {{p}}
+        <svg>
+            <clipPath></clipPath>
+        </svg>
+    </li>
+</ul>
+"#
+    );
+
+    // texts in raw text elements are not escaped
+    let pat = Pattern::new("<script>{{x}} &amp;&amp; y</script><p>a &amp; b</p>").unwrap();
+    assert_eq!(
+        round_trip(&pat),
+        "<script>{{x}} &amp;&amp; y</script>\n<p>a &amp; b</p>\n"
+    );
 }
 
 #[test]
-fn test_basic() {
+fn test_comment() {
     let doc = r#"
 <!DOCTYPE html>
 <html lang="en">
-    <head>
-    </head>
     <body>
         <ul>
-            <li>1</li>
-            <li>2</li>
-            <li>3</li>
+            <li><!-- pid:123 --><a href="/a">Foo</a></li>
+            <!-- ad -->
+            <li><!-- pid:456 --><a href="/b">Bar</a></li>
         </ul>
+        <!--[if IE]><p>old browser</p><![endif]-->
     </body>
 </html>
 "#;
 
-    let pat = Pattern::new(
-        r#"
-<ul>
-    <li>{{hoge}}</li>
-</ul>
-"#,
-    )
-    .unwrap();
-
+    let pat = Pattern::new(r#"<li><!-- pid:{{pid}} --><a>{{title}}</a></li>"#).unwrap();
     let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 3);
-    assert_eq!(ms[0]["hoge"], "1");
-    assert_eq!(ms[1]["hoge"], "2");
-    assert_eq!(ms[2]["hoge"], "3");
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["pid"], "123");
+    assert_eq!(ms[0]["title"], "Foo");
+    assert_eq!(ms[1]["pid"], "456");
 
     let pat = Pattern::new(
         r#"
 <ul>
-    <li>{{hoge}}</li>
-    <li>{{moge}}</li>
+    <li><!-- pid:{{a}} --></li>
+    <li><!-- pid:{{b}} --></li>
 </ul>
 "#,
     )
     .unwrap();
+    let ms = pat.matches(doc);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["a"], "123");
+    assert_eq!(ms[0]["b"], "456");
 
+    let pat = Pattern::new(r#"<!--[if IE]>{{x}}<![endif]-->"#).unwrap();
     let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 2);
-    assert_eq!(ms[0]["hoge"], "1");
-    assert_eq!(ms[0]["moge"], "2");
-    assert_eq!(ms[1]["hoge"], "2");
-    assert_eq!(ms[1]["moge"], "3");
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["x"], "<p>old browser</p>");
 }
 
 #[test]
-fn test_attribute() {
+fn test_json() {
     let doc = r#"
 <!DOCTYPE html>
 <html lang="en">
     <head>
+        <script type="application/ld+json">
+        {
+            "@type": "Product",
+            "name": "Foo",
+            "offers": [{ "price": 1200 }, { "price": "980" }]
+        }
+        </script>
     </head>
     <body>
-        <div class="foo bar baz">
-            hello
-        </div>
+        <div data-props='{"id": 42}'>Foo</div>
     </body>
 </html>
 "#;
 
-    let pat = Pattern::new(r#"<div>{{foo}}</div>"#).unwrap();
+    let pat = Pattern::new(r#"<script type="application/ld+json">{{name:json:$.name}}</script>"#)
+        .unwrap();
     let ms = pat.matches(doc);
     assert_eq!(ms.len(), 1);
-    assert_eq!(ms[0]["foo"], "hello");
+    assert_eq!(ms[0]["name"], "Foo");
 
-    let pat = Pattern::new(r#"<div class="">{{foo}}</div>"#).unwrap();
+    let pat = Pattern::new(
+        r#"<script type="application/ld+json">{{price:json:$.offers[*].price}}</script>"#,
+    )
+    .unwrap();
     let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 1);
-    assert_eq!(ms[0]["foo"], "hello");
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["price"], "1200");
+    assert_eq!(ms[1]["price"], "980");
 
-    let pat = Pattern::new(r#"<div class="foo">{{foo}}</div>"#).unwrap();
+    let pat = Pattern::new(r#"<div data-props="{{id:json:$.id}}">{{name}}</div>"#).unwrap();
     let ms = pat.matches(doc);
     assert_eq!(ms.len(), 1);
-    assert_eq!(ms[0]["foo"], "hello");
+    assert_eq!(ms[0]["id"], "42");
+    assert_eq!(ms[0]["name"], "Foo");
 
-    let pat = Pattern::new(r#"<div class="foo bar">{{foo}}</div>"#).unwrap();
-    let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 1);
-    assert_eq!(ms[0]["foo"], "hello");
+    let pat = Pattern::new(r#"<div data-props="{{props:json}}"></div>"#).unwrap();
+    assert_eq!(pat.matches(doc)[0]["props"], r#"{"id":42}"#);
 
-    let pat = Pattern::new(r#"<div class="foo bar baz">{{foo}}</div>"#).unwrap();
-    let ms = pat.matches(doc);
+    // JSON in a part of the text
+    let pat = Pattern::new(r#"<script>var d = {{d:json:$.a.b}};</script>"#).unwrap();
+    let ms = pat.matches(r#"<script>var d = {"a":{"b":1}};</script>"#);
     assert_eq!(ms.len(), 1);
-    assert_eq!(ms[0]["foo"], "hello");
+    assert_eq!(ms[0]["d"], "1");
 
-    let pat = Pattern::new(r#"<div class="baz foo bar">{{foo}}</div>"#).unwrap();
+    assert!(Pattern::new(r#"<script>{{x:json:offers}}</script>"#).is_err());
+}
+
+#[test]
+fn test_xml() {
+    let doc = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:m="http://search.yahoo.com/mrss/">
+    <title>Example Feed</title>
+    <entry>
+        <title>Foo</title>
+        <link href="http://example.org/foo"/>
+        <m:thumbnail url="foo.png"/>
+        <updatedAt><![CDATA[2020-01-01]]></updatedAt>
+    </entry>
+    <entry>
+        <title>Bar</title>
+        <link href="http://example.org/bar"/>
+        <updatedAt>2020-01-02</updatedAt>
+    </entry>
+</feed>
+"#;
+
+    let pat = Pattern::new_xml(
+        r#"
+<entry>
+    <title>{{title}}</title>
+    <link href="{{url}}"/>
+</entry>
+"#,
+    )
+    .unwrap();
     let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 1);
-    assert_eq!(ms[0]["foo"], "hello");
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["title"], "Foo");
+    assert_eq!(ms[0]["url"], "http://example.org/foo");
+    assert_eq!(ms[1]["title"], "Bar");
 
-    let pat = Pattern::new(r#"<div class="hoge">{{foo}}</div>"#).unwrap();
+    // Names are case-sensitive
+    let pat = Pattern::new_xml(r#"<updatedAt>{{date}}</updatedAt>"#).unwrap();
     let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 0);
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["date"], "2020-01-01");
+    let pat = Pattern::new_xml(r#"<updatedat>{{date}}</updatedat>"#).unwrap();
+    assert_eq!(pat.matches(doc).len(), 0);
 
-    let pat = Pattern::new(r#"<div id="">{{foo}}</div>"#).unwrap();
+    // Prefixed names are compared by namespace
+    let pat = Pattern::new_xml(r#"<media:thumbnail url="{{img}}"/>"#).unwrap();
     let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 0);
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["img"], "foo.png");
+    let pat = Pattern::new_xml(r#"<atom:thumbnail url="{{img}}"/>"#).unwrap();
+    assert_eq!(pat.matches(doc).len(), 0);
+
+    let pat = Pattern::new_xml(
+        r#"<x:entry xmlns:x="http://www.w3.org/2005/Atom"><x:title>{{title}}</x:title></x:entry>"#,
+    )
+    .unwrap();
+    assert_eq!(pat.matches(doc).len(), 2);
+
+    assert!(Pattern::new_xml("<entry>").is_err());
+    assert_eq!(pat.matches("<feed>"), vec![]);
+    assert!(matches!(
+        pat.matches_with_options("<feed>", &MatchOptions::default()),
+        Err(MatchError::InvalidDocument(_))
+    ));
 }
 
 #[test]
-fn test_attribute_pattern() {
-    let doc = r#"
+fn test_svg() {
+    let doc = r##"
 <!DOCTYPE html>
 <html lang="en">
-    <head>
-    </head>
     <body>
-        <a href="https://www.google.com">Google</a>
-        <p>
-            <a href="https://github.com">GitHub</a>
-        </p>
+        <svg viewBox="0 0 10 10">
+            <use xlink:href="#icon-star"></use>
+        </svg>
     </body>
 </html>
-"#;
+"##;
 
-    let pat = Pattern::new(r#"<a href="{{url}}">{{link}}</a>"#).unwrap();
+    let pat =
+        Pattern::new(r#"<svg viewBox="{{box}}"><use xlink:href="{{ref}}"></use></svg>"#).unwrap();
     let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 2);
-    assert_eq!(ms[0]["url"], "https://www.google.com");
-    assert_eq!(ms[0]["link"], "Google");
-    assert_eq!(ms[1]["url"], "https://github.com");
-    assert_eq!(ms[1]["link"], "GitHub");
+    assert_eq!(ms.len(), 1);
+    assert_eq!(ms[0]["box"], "0 0 10 10");
+    assert_eq!(ms[0]["ref"], "#icon-star");
 }
 
 #[test]
-fn test_skip() {
+fn test_fragment() {
     let doc = r#"
 <!DOCTYPE html>
 <html lang="en">
-    <head>
-    </head>
     <body>
-        <ul>
-            <li>1</li>
-            <li>2</li>
-            <li>3</li>
-        </ul>
+        <table>
+            <thead><tr><th>Name</th><th>Price</th></tr></thead>
+            <tbody>
+                <tr><td>Apple</td><td>100</td></tr>
+                <tr><td>Orange</td><td>80</td></tr>
+            </tbody>
+        </table>
+        <select><option value="1">One</option><option value="2">Two</option></select>
+        <ol><li>First</li></ol>
     </body>
 </html>
 "#;
 
     let pat = Pattern::new(
         r#"
-<ul>
-    <li>{{hoge}}</li>
-    ...
-    <li>{{moge}}</li>
-</ul>
+<tr><td>{{name}}</td><td>{{price}}</td></tr>
 "#,
     )
     .unwrap();
-
     let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 3);
-    assert_eq!(ms[0]["hoge"], "1");
-    assert_eq!(ms[0]["moge"], "2");
-    assert_eq!(ms[1]["hoge"], "1");
-    assert_eq!(ms[1]["moge"], "3");
-    assert_eq!(ms[2]["hoge"], "2");
-    assert_eq!(ms[2]["moge"], "3");
-}
-
-#[test]
-fn test_all_match() {
-    let doc = r#"
-<!DOCTYPE html>
-<html lang="en">
-    <head>
-    </head>
-    <body>
-        Hello
-        <span>hoge</span>
-        World
-    </body>
-</html>
-"#;
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[1]["name"], "Orange");
+    assert_eq!(ms[1]["price"], "80");
 
-    let pat = Pattern::new(r#"<body>{{body:*}}</body>"#).unwrap();
+    let pat = Pattern::new("<th>{{col}}</th>").unwrap();
+    assert_eq!(pat.matches(doc).len(), 2);
 
+    let pat = Pattern::new(r#"<option value="{{value}}">{{label}}</option>"#).unwrap();
     let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 1);
-    assert_eq!(ms[0]["body"], "Hello<span>hoge</span>World");
-}
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["value"], "1");
+    assert_eq!(ms[1]["label"], "Two");
 
-#[test]
-fn test_partial() {
-    let doc = r#"
-<!DOCTYPE html>
-<html lang="en">
-    <head>
-    </head>
-    <body>
-        <ul>
-            <li>Test 1, 2</li>
-            <li>Test 3, 4</li>
-            <li>Test 5, 6</li>
-        </ul>
-    </body>
-</html>
-"#;
+    let pat = Pattern::new("<li>{{item}}</li>").unwrap();
+    assert_eq!(pat.matches(doc)[0]["item"], "First");
 
-    let pat = Pattern::new(r#"<ul>Test {{foo}}, {{bar}}</ul>"#).unwrap();
+    // rows without `<tbody>` match rows in any section
+    let pat = Pattern::new("<table><tr><th>{{a}}</th></tr></table>").unwrap();
+    assert_eq!(pat.matches(doc).len(), 2);
+    let pat = Pattern::new("<table><tbody><tr><th>{{a}}</th></tr></tbody></table>").unwrap();
+    assert!(pat.matches(doc).is_empty());
+
+    // decided by each table and section
+    let pat = Pattern::new(
+        "<div><table><tr><th>{{a}}</th></tr></table><table><tbody><tr><td>x</td></tr></tbody></table></div>",
+    )
+    .unwrap();
+    assert_eq!(pat.to_string().matches("<tbody>").count(), 1);
+    let pat = Pattern::new(
+        "<table><tr><th>{{a}}</th></tr><tbody><tr><td>{{b}}</td></tr></tbody></table>",
+    )
+    .unwrap();
+    assert_eq!(pat.to_string().matches("<tbody>").count(), 1);
+    let pat = Pattern::new("<caption>{{c}}</caption><tr><th>{{a}}</th></tr>").unwrap();
+    assert!(!pat.to_string().contains("<tbody>"));
+    let pat = Pattern::new(
+        "<div><table><tr><td>{{a}}</td></tr></table><table><tr><th>{{b}}</th></tr></table></div>",
+    )
+    .unwrap();
+    assert!(!pat.to_string().contains("<tbody>"));
+    let doc2 = "<div><table><tr><td>x</td></tr></table><table><thead><tr><th>y</th></tr></thead></table></div>";
+    assert_eq!(pat.matches(doc2)[0]["b"], "y");
+    let pat =
+        Pattern::new("<table><tr><td><table><tr><th>{{a}}</th></tr></table></td></tr></table>")
+            .unwrap();
+    assert!(!pat.to_string().contains("<tbody>"));
+    let doc2 = "<table><tbody><tr><td><table><thead><tr><th>y</th></tr></thead></table></td></tr></tbody></table>";
+    assert_eq!(pat.matches(doc2)[0]["a"], "y");
+    // the first table is dropped by the parser
+    let pat = Pattern::new(
+        "<div><select><table><tbody></tbody></table></select><table><tr><th>{{a}}</th></tr></table></div>",
+    )
+    .unwrap();
+    assert!(!pat.to_string().contains("<tbody>"));
 
+    let pat = Pattern::new_fragment("<td>{{a}}</td>\n<td>{{b}}</td>", "tr").unwrap();
     let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 3);
-    assert_eq!(ms[0]["foo"], "1");
-    assert_eq!(ms[0]["bar"], "2");
-    assert_eq!(ms[1]["foo"], "3");
-    assert_eq!(ms[1]["bar"], "4");
-    assert_eq!(ms[2]["foo"], "5");
-    assert_eq!(ms[2]["bar"], "6");
+    assert_eq!(ms.len(), 2);
+    assert_eq!(ms[0]["a"], "Apple");
+    assert_eq!(ms[0]["b"], "100");
 }
 
 #[test]
-fn test_attr_partial() {
-    let doc = r#"
-<!DOCTYPE html>
-<html lang="en">
-    <head>
-    </head>
-    <body>
-        <a href="/users/foo/info"></a>
-        <a href="/users/bar/info"></a>
-        <a href="/users/baz/info"></a>
-    </body>
-</html>
-"#;
-
-    let pat = Pattern::new(r#"<a href="/users/{{user}}/info"></a>"#).unwrap();
+fn test_threads() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let pat = Pattern::new(r#"<li class="item">{{name}}</li>"#).unwrap();
+    assert_send_sync(&pat);
+
+    let docs = (0..8)
+        .map(|i| format!(r#"<ul><li class="item">{}</li><li>x</li></ul>"#, i))
+        .collect::<Vec<_>>();
+    let pat = &pat;
+    let ms = std::thread::scope(|s| {
+        let hs = docs
+            .iter()
+            .map(|doc| s.spawn(move || pat.matches(doc)))
+            .collect::<Vec<_>>();
+        hs.into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    for (i, m) in ms.iter().enumerate() {
+        assert_eq!(m.len(), 1);
+        assert_eq!(m[0]["name"], i.to_string());
+    }
 
-    let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 3);
-    assert_eq!(ms[0]["user"], "foo");
-    assert_eq!(ms[1]["user"], "bar");
-    assert_eq!(ms[2]["user"], "baz");
+    #[cfg(feature = "rayon")]
+    assert_eq!(pat.par_matches(&docs), ms);
 }
 
+// Compares matching on the view of the parsed tree with matching on a filtered copy,
+// which had been done before.
+// Run with `cargo test --release bench_document_view -- --ignored --nocapture`.
 #[test]
-fn test_table_skip() {
-    let doc = r#"
-<!DOCTYPE html>
-<html lang="en">
-    <head>
-    </head>
-    <body>
-        <table>
-            <tr><th>AAA</th><td>aaa</td></tr>
-            <tr><th>BBB</th><td>bbb</td></tr>
-            <tr><th>CCC</th><td>ccc</td></tr>
-            <tr><th>DDD</th><td>ddd</td></tr>
-            <tr><th>EEE</th><td>eee</td></tr>
-        </table>
-    </body>
-</html>
-"#;
+#[ignore]
+fn bench_document_view() {
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<body>\n<div id=\"main\">\n");
+    let mut i = 0;
+    while html.len() < 1_000_000 {
+        html.push_str(&format!(
+            r#"
+    <div class="entry entry-{0}" data-id="{0}">
+        <h2 class="title"><a href="/entry/{0}">Entry {0}</a></h2>
+        <!-- meta -->
+        <p class="meta">
+            <span class="author">user{1}</span>
+            <span class="date">2020-01-{2:02}</span>
+        </p>
+        <p class="body">Lorem ipsum dolor sit amet, consectetur adipiscing elit.</p>
+    </div>
+"#,
+            i,
+            i % 100,
+            i % 28 + 1
+        ));
+        i += 1;
+    }
+    html.push_str("</div>\n</body>\n</html>\n");
 
     let pat = Pattern::new(
         r#"
-<table subseq>
-    <tr><th>AAA</th><td>{{a}}</td></tr>
-    <tr><th>BBB</th><td>{{b}}</td></tr>
-    <tr><th>DDD</th><td>{{d}}</td></tr>
-</table>
+<div class="entry">
+    <h2><a href="{{url}}">{{title}}</a></h2>
+    <p><span class="author">{{author}}</span></p>
+</div>
 "#,
     )
     .unwrap();
+    let opts = MatchOptions::default();
+    let doc = parse_html().one(html.as_str());
+    let iters = 10;
+
+    let start = Instant::now();
+    for _ in 0..iters {
+        let index = Index::new(&doc);
+        assert_eq!(pat.match_tree(&doc, &index, &opts).unwrap().len(), i);
+    }
+    let view = start.elapsed() / iters as u32;
+
+    let start = Instant::now();
+    for _ in 0..iters {
+        let copy = Filter {
+            mode: opts.whitespace,
+            preserve: &opts.preserve_elements,
+            comments: false,
+        }
+        .filter(doc.clone(), false)
+        .unwrap();
+        let index = Index::new(&copy);
+        assert_eq!(pat.match_tree(&copy, &index, &opts).unwrap().len(), i);
+    }
+    let copy = start.elapsed() / iters as u32;
 
-    let ms = pat.matches(doc);
-    assert_eq!(ms.len(), 1);
-    assert_eq!(ms[0]["a"], "aaa");
-    assert_eq!(ms[0]["b"], "bbb");
-    assert_eq!(ms[0]["d"], "ddd");
+    // The index is built once for `Document`.
+    let index = Index::new(&doc);
+    let start = Instant::now();
+    for _ in 0..iters {
+        assert_eq!(pat.match_tree(&doc, &index, &opts).unwrap().len(), i);
+    }
+    let indexed = start.elapsed() / iters as u32;
+
+    eprintln!(
+        "{} bytes, {} entries: view {:?}, copy {:?}, view with built index {:?}",
+        html.len(),
+        i,
+        view,
+        copy,
+        indexed
+    );
+}
+
+#[test]
+fn test_diagnostics() {
+    let warnings = |src: &str| Pattern::new_with_diagnostics(src).unwrap().1;
+
+    assert!(warnings("<ul><li>{{a}}<li>{{b}}</ul>").is_empty());
+    assert!(warnings("<table><tr><td>a</td></tr><tr><td>{{x}}</td></tr></table>").is_empty());
+    assert!(warnings("<html><body><p>{{x}}</p></body></html>").is_empty());
+
+    let ws = warnings("<div>\n  <p><div>{{x}}</div>\n</div>");
+    assert_eq!(ws.len(), 1);
+    assert_eq!(ws[0].kind, WarningKind::AutoClosed);
+    assert_eq!((ws[0].line, ws[0].column), (2, 6));
+    assert_eq!(ws[0].message, "<p> at 2:3 is closed before <div>");
+
+    let ws = warnings("<table><div>{{x}}</div><tr><td>{{y}}</td></tr></table>");
+    assert_eq!(ws.len(), 1);
+    assert_eq!(ws[0].kind, WarningKind::FosterParented);
+
+    let ws = warnings("<table><td>{{x}}</td></table>");
+    assert_eq!(ws.len(), 1);
+    assert_eq!(ws[0].kind, WarningKind::AutoInserted);
+    assert_eq!(ws[0].message, "<tr> is inserted around <td> at 1:8");
+
+    let ws = warnings("<body><p>{{x}}</p><body><p>{{y}}</p>");
+    assert_eq!(ws.len(), 1);
+    assert_eq!(ws[0].kind, WarningKind::Dropped);
+    assert_eq!((ws[0].line, ws[0].column), (1, 19));
+
+    // elements are paired by positions, and SVG names are compared ignoring case
+    assert!(warnings(
+        "<table><tr><th>{{a}}</th></tr><tbody><tr><td>{{b}}</td></tr></tbody></table>"
+    )
+    .is_empty());
+    assert!(warnings(
+        "<svg><clipPath><rect/></clipPath><foreignObject><p>{{x}}</p></foreignObject></svg>"
+    )
+    .is_empty());
+    let ws = warnings("<table><td>{{x}}</td><td>{{y}}</td></table>");
+    assert_eq!(ws.len(), 1);
+    assert_eq!(ws[0].kind, WarningKind::AutoInserted);
+
+    // parse errors are reported as warnings
+    assert!(Pattern::new("<p><div>{{x}}</div></p>").is_err());
+    let ws = warnings("<p><div>{{x}}</div></p>");
+    assert_eq!(ws.len(), 2);
+    assert_eq!(ws[0].kind, WarningKind::AutoClosed);
+    assert_eq!(ws[1].kind, WarningKind::AutoInserted);
+    let ws = warnings("<ul><li>{{a}}</li></li></ul>");
+    assert_eq!(ws.len(), 1);
+    assert_eq!(ws[0].kind, WarningKind::Dropped);
+    assert_eq!(ws[0].message, "</li> is ignored by the parser");
 }
@@ -0,0 +1,339 @@
+// Partial matching for ranking candidates when a pattern does not match exactly.
+// Each node and attribute of the pattern has a weight of 1. A pattern node which
+// does not match exactly is matched part by part, and the candidate gets the weights
+// of the matched parts. Parts which do not match are reported by their paths.
+
+use crate::pattern::{PatternData, PatternNode};
+use crate::{is_skip, is_special_attr, is_var, match_attributes, match_name, node_id, Matcher};
+use kuchiki::{Attributes, NodeRef};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+/// Partial match with its score
+///
+/// Returned by [`Pattern::best_matches`](crate::Pattern::best_matches).
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartialMatch {
+    pub bindings: BTreeMap<String, String>,
+    /// Ratio of matched nodes and attributes of the pattern, 1 for exact matches
+    pub score: f64,
+    /// Paths of pattern nodes and attributes which do not match, e.g. `tr > th > "Price"`
+    pub unmatched: Vec<String>,
+}
+
+#[derive(Clone, Default)]
+struct Partial {
+    weight: f64,
+    bindings: BTreeMap<String, String>,
+    unmatched: Vec<String>,
+}
+
+impl Partial {
+    fn append(&mut self, other: Partial) {
+        self.weight += other.weight;
+        self.bindings.extend(other.bindings);
+        self.unmatched.extend(other.unmatched);
+    }
+}
+
+type Key = (*const kuchiki::Node, *const PatternNode);
+
+pub(crate) struct PartialMatcher<'a> {
+    matcher: &'a Matcher<'a>,
+    paths: HashMap<*const PatternNode, String>,
+    // Results of `best`, `within` and `align_within`
+    nodes: RefCell<HashMap<Key, Option<Partial>>>,
+    subtrees: RefCell<HashMap<Key, Option<Partial>>>,
+    siblings: RefCell<HashMap<Key, Partial>>,
+}
+
+impl<'a> PartialMatcher<'a> {
+    pub(crate) fn new(matcher: &'a Matcher<'a>, roots: &[&PatternNode]) -> PartialMatcher<'a> {
+        let mut paths = HashMap::new();
+        add_paths(&mut paths, roots, "");
+        PartialMatcher {
+            matcher,
+            paths,
+            nodes: RefCell::new(HashMap::new()),
+            subtrees: RefCell::new(HashMap::new()),
+            siblings: RefCell::new(HashMap::new()),
+        }
+    }
+
+    // Best `k` partial matches in `doc`, by score and then in document order.
+    pub(crate) fn best_matches(
+        &self,
+        doc: &NodeRef,
+        roots: &[&PatternNode],
+        k: usize,
+    ) -> Vec<PartialMatch> {
+        let total = roots.iter().map(|r| weight(r)).sum::<f64>();
+        let mut ms = vec![];
+        for node in doc.inclusive_descendants() {
+            let m = match roots {
+                [root] => self.best(&node, root),
+                _ => {
+                    // Multiple roots are matched to children of a node.
+                    let doc_cs = self.matcher.view.children(&node);
+                    Some(self.align(&doc_cs, roots, false)).filter(|r| r.weight > 0.0)
+                }
+            };
+            if let Some(m) = m {
+                ms.push(PartialMatch {
+                    bindings: m.bindings,
+                    score: m.weight / total,
+                    unmatched: m.unmatched,
+                });
+            }
+        }
+
+        ms.sort_by(|a, b| b.score.total_cmp(&a.score));
+        let mut ret: Vec<PartialMatch> = vec![];
+        for m in ms {
+            if ret.len() >= k {
+                break;
+            }
+            // Ancestors often have the same results by the subset rule.
+            if !ret.contains(&m) {
+                ret.push(m);
+            }
+        }
+        ret
+    }
+
+    // Matches `pattern` to `doc` itself.
+    fn best(&self, doc: &NodeRef, pattern: &PatternNode) -> Option<Partial> {
+        let key = (node_id(doc), pattern as *const _);
+        if let Some(ret) = self.nodes.borrow().get(&key) {
+            return ret.clone();
+        }
+        let ret = self.best_inner(doc, pattern);
+        self.nodes.borrow_mut().insert(key, ret.clone());
+        ret
+    }
+
+    fn best_inner(&self, doc: &NodeRef, pattern: &PatternNode) -> Option<Partial> {
+        if pattern.as_text().and_then(is_var).is_some_and(|r| r.whole) {
+            return None;
+        }
+
+        // Errors by limits are regarded as mismatches.
+        let exact = self.matcher.match_subtree(doc, pattern, true);
+        if let Some(m) = exact.unwrap_or_default().into_iter().next() {
+            return Some(Partial {
+                weight: weight(pattern) * m.score,
+                bindings: m.bindings,
+                unmatched: vec![],
+            });
+        }
+
+        let (e1, e2) = (doc.as_element()?, pattern.as_element()?);
+        if !match_name(&e1.name, &e2.name) {
+            return None;
+        }
+
+        let mut ret = Partial {
+            weight: 1.0,
+            ..Partial::default()
+        };
+        let path = self.path(pattern);
+        for (name, value) in e2.attributes.map.iter() {
+            if is_special_attr(name) {
+                continue;
+            }
+            let attrs = Attributes {
+                map: std::iter::once((name.clone(), value.clone())).collect(),
+            };
+            let ms = match_attributes(
+                &e1.attributes.borrow(),
+                &attrs,
+                pattern.fold,
+                &self.matcher.capture,
+            );
+            match ms.into_iter().next() {
+                Some(bindings) => ret.append(Partial {
+                    weight: 1.0,
+                    bindings,
+                    unmatched: vec![],
+                }),
+                None => ret.unmatched.push(format!("{}[{}]", path, &*name.local)),
+            }
+        }
+
+        // Whole variables and `subseq` are handled by the exact matcher.
+        let subseq = e2
+            .attributes
+            .map
+            .keys()
+            .any(|k| k.local.as_ref() == "subseq");
+        let doc_cs = self.matcher.view.children(doc);
+        let exact = self
+            .matcher
+            .match_siblings(&doc_cs, &pattern.children, subseq);
+        match exact.unwrap_or_default().into_iter().next() {
+            Some(m) => ret.append(Partial {
+                weight: weights(&pattern.children) * m.score,
+                bindings: m.bindings,
+                unmatched: vec![],
+            }),
+            None => {
+                let children = pattern.children.iter().collect::<Vec<_>>();
+                ret.append(self.align_within(doc, &children));
+            }
+        }
+        Some(ret)
+    }
+
+    // Matches `pattern` to `doc` or its descendants.
+    fn within(&self, doc: &NodeRef, pattern: &PatternNode) -> Option<Partial> {
+        let key = (node_id(doc), pattern as *const _);
+        if let Some(ret) = self.subtrees.borrow().get(&key) {
+            return ret.clone();
+        }
+
+        let mut ret = self.best(doc, pattern);
+        if pattern.as_text().is_none() {
+            for child in self.matcher.view.children(doc) {
+                if let Some(m) = self.within(&child, pattern) {
+                    if ret.as_ref().is_none_or(|r| m.weight > r.weight) {
+                        ret = Some(m);
+                    }
+                }
+            }
+        }
+
+        self.subtrees.borrow_mut().insert(key, ret.clone());
+        ret
+    }
+
+    // Matches `pattern` to children of `doc` or to children of one of its descendants.
+    fn align_within(&self, doc: &NodeRef, pattern: &[&PatternNode]) -> Partial {
+        // Pattern nodes are children of the same node.
+        let key = (
+            node_id(doc),
+            pattern.first().map_or(std::ptr::null(), |r| *r),
+        );
+        if let Some(ret) = self.siblings.borrow().get(&key) {
+            return ret.clone();
+        }
+
+        let doc_cs = self.matcher.view.children(doc);
+        let mut ret = self.align(&doc_cs, pattern, true);
+        for child in doc_cs.iter().filter(|r| r.as_element().is_some()) {
+            let m = self.align_within(child, pattern);
+            if m.weight > ret.weight {
+                ret = m;
+            }
+        }
+
+        self.siblings.borrow_mut().insert(key, ret.clone());
+        ret
+    }
+
+    // Matches `pattern` to `doc` in order, skipping nodes of both.
+    // Pattern nodes match to descendants of `doc` if `descendants`.
+    fn align(&self, doc: &[NodeRef], pattern: &[&PatternNode], descendants: bool) -> Partial {
+        let pattern = pattern
+            .iter()
+            .filter(|r| !r.as_text().is_some_and(is_skip))
+            .collect::<Vec<_>>();
+
+        // dp[i][j]: best result for `doc[i..]` and `pattern[j..]`
+        let (n, m) = (doc.len(), pattern.len());
+        let mut dp = vec![vec![Partial::default(); m + 1]; n + 1];
+        for j in (0..m).rev() {
+            dp[n][j] = dp[n][j + 1].clone();
+            dp[n][j].unmatched.insert(0, self.path(pattern[j]));
+        }
+        for i in (0..n).rev() {
+            for j in (0..=m).rev() {
+                let mut best = dp[i + 1][j].clone();
+                if j < m {
+                    let mut skip = dp[i][j + 1].clone();
+                    skip.unmatched.insert(0, self.path(pattern[j]));
+                    let matched = if descendants {
+                        self.within(&doc[i], pattern[j])
+                    } else {
+                        self.best(&doc[i], pattern[j])
+                    };
+                    if let Some(mut matched) = matched {
+                        matched.append(dp[i + 1][j + 1].clone());
+                        if matched.weight >= best.weight {
+                            best = matched;
+                        }
+                    }
+                    if skip.weight > best.weight {
+                        best = skip;
+                    }
+                }
+                dp[i][j] = best;
+            }
+        }
+        dp.swap_remove(0).swap_remove(0)
+    }
+
+    fn path(&self, pattern: &PatternNode) -> String {
+        self.paths
+            .get(&(pattern as *const _))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+// Total weight of the subtree.
+fn weight(pattern: &PatternNode) -> f64 {
+    let attrs = pattern.as_element().map_or(0, |e| {
+        e.attributes
+            .map
+            .keys()
+            .filter(|r| !is_special_attr(r))
+            .count()
+    });
+    1.0 + attrs as f64 + weights(&pattern.children)
+}
+
+fn weights(nodes: &[PatternNode]) -> f64 {
+    nodes
+        .iter()
+        .filter(|r| !r.as_text().is_some_and(is_skip))
+        .map(weight)
+        .sum()
+}
+
+// Paths of pattern nodes, e.g. `table > tr:nth-of-type(2) > td`.
+fn add_paths(
+    paths: &mut HashMap<*const PatternNode, String>,
+    nodes: &[&PatternNode],
+    parent: &str,
+) {
+    for node in nodes {
+        let step = match &node.data {
+            PatternData::Element(e) => {
+                let same = |r: &&&PatternNode| r.as_element().is_some_and(|r| r.name == e.name);
+                let count = nodes.iter().filter(same).count();
+                if count > 1 {
+                    let nth = nodes
+                        .iter()
+                        .take_while(|r| !std::ptr::eq(**r, *node))
+                        .filter(same)
+                        .count();
+                    format!("{}:nth-of-type({})", &*e.name.local, nth + 1)
+                } else {
+                    e.name.local.to_string()
+                }
+            }
+            PatternData::Text(text) => format!("{:?}", text.trim()),
+            PatternData::Comment(text) => format!("<!--{}-->", text),
+            PatternData::ProcessingInstruction(target, _) => format!("<?{}?>", target),
+            PatternData::Document | PatternData::Doctype => continue,
+        };
+        let path = if parent.is_empty() {
+            step
+        } else {
+            format!("{} > {}", parent, step)
+        };
+        let children = node.children.iter().collect::<Vec<_>>();
+        add_paths(paths, &children, &path);
+        paths.insert(*node as *const _, path);
+    }
+}
@@ -0,0 +1,111 @@
+// Trees of compiled patterns.
+// Patterns are parsed into kuchiki's trees and converted to this tree,
+// which does not use `Rc`, so that patterns can be shared between threads.
+
+use crate::fold::Fold;
+use html5ever::QualName;
+use kuchiki::{Attributes, NodeData, NodeRef};
+
+#[derive(Clone, Debug)]
+pub(crate) struct PatternNode {
+    pub(crate) data: PatternData,
+    pub(crate) children: Vec<PatternNode>,
+    // Folding of texts, given by the builder and `fold` attributes of ancestors
+    pub(crate) fold: Fold,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum PatternData {
+    Document,
+    Doctype,
+    Element(PatternElement),
+    Text(String),
+    Comment(String),
+    ProcessingInstruction(String, String),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct PatternElement {
+    pub(crate) name: QualName,
+    pub(crate) attributes: Attributes,
+}
+
+impl PatternNode {
+    pub(crate) fn new(node: &NodeRef, fold: Fold) -> Result<PatternNode, String> {
+        let mut fold = fold;
+        if let Some(e) = node.as_element() {
+            if let Some(value) = e.attributes.borrow().get("fold") {
+                fold = fold.union(Fold::parse(value)?);
+            }
+        }
+
+        let data = match node.data() {
+            NodeData::Document(_) | NodeData::DocumentFragment => PatternData::Document,
+            NodeData::Doctype(_) => PatternData::Doctype,
+            NodeData::Element(e) => PatternData::Element(PatternElement {
+                name: e.name.clone(),
+                attributes: e.attributes.borrow().clone(),
+            }),
+            NodeData::Text(text) => PatternData::Text(text.borrow().clone()),
+            NodeData::Comment(text) => PatternData::Comment(text.borrow().clone()),
+            NodeData::ProcessingInstruction(pi) => {
+                let pi = pi.borrow();
+                PatternData::ProcessingInstruction(pi.0.clone(), pi.1.clone())
+            }
+        };
+
+        Ok(PatternNode {
+            data,
+            children: node
+                .children()
+                .map(|r| PatternNode::new(&r, fold))
+                .collect::<Result<_, _>>()?,
+            fold,
+        })
+    }
+
+    pub(crate) fn as_element(&self) -> Option<&PatternElement> {
+        match &self.data {
+            PatternData::Element(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_text(&self) -> Option<&str> {
+        match &self.data {
+            PatternData::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_comment(&self) -> Option<&str> {
+        match &self.data {
+            PatternData::Comment(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_document(&self) -> bool {
+        matches!(self.data, PatternData::Document)
+    }
+
+    pub(crate) fn is_doctype(&self) -> bool {
+        matches!(self.data, PatternData::Doctype)
+    }
+
+    pub(crate) fn is_comment_like(&self) -> bool {
+        matches!(
+            self.data,
+            PatternData::Comment(_) | PatternData::ProcessingInstruction(..)
+        )
+    }
+
+    // Nodes of the subtree in preorder.
+    pub(crate) fn inclusive_descendants(&self) -> Vec<&PatternNode> {
+        let mut ret = vec![self];
+        for child in self.children.iter() {
+            ret.extend(child.inclusive_descendants());
+        }
+        ret
+    }
+}
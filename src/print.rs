@@ -0,0 +1,265 @@
+// Printing patterns: `Display` in the pattern syntax, and `Debug` as a tree of nodes.
+// Patterns are printed as they are parsed, e.g. with elements moved by the HTML parser,
+// and the printed source is parsed to the same pattern. Alternatives by `or` have no
+// syntax, so that each of them is printed and parsed separately.
+
+use crate::pattern::{PatternData, PatternElement, PatternNode};
+use crate::{is_skip, is_special_attr, is_var, Pattern, Syntax};
+use std::collections::HashMap;
+use std::fmt;
+
+impl fmt::Display for Pattern {
+    // Alternatives combined by `or` are printed in order, separated by empty lines.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        for (i, alt) in std::iter::once(self)
+            .chain(self.alternatives.iter())
+            .enumerate()
+        {
+            if i > 0 {
+                out.push('\n');
+            }
+            let mut printer = Printer {
+                syntax: alt.syntax,
+                scope: HashMap::new(),
+                out: &mut out,
+            };
+            for child in alt.pattern.children.iter() {
+                printer.write(child, 0, false);
+            }
+        }
+        f.write_str(&out)
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, alt) in std::iter::once(self)
+            .chain(self.alternatives.iter())
+            .enumerate()
+        {
+            if i > 0 {
+                writeln!(f, "Or")?;
+            }
+            let kind = if alt.document { "document" } else { "fragment" };
+            write!(f, "Pattern ({:?} {}", alt.syntax, kind)?;
+            if let Some(fuzzy) = &alt.fuzzy {
+                write!(
+                    f,
+                    ", fuzzy: {} chars or {} words",
+                    fuzzy.max_distance, fuzzy.min_similarity
+                )?;
+            }
+            writeln!(f, ")")?;
+            for child in alt.pattern.children.iter() {
+                write_tree(f, child, 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Printer<'a> {
+    syntax: Syntax,
+    // Namespaces declared by ancestors in XML, by prefixes
+    scope: HashMap<String, String>,
+    out: &'a mut String,
+}
+
+impl<'a> Printer<'a> {
+    // Texts in raw text elements are not escaped, since the parser does not decode them.
+    fn write(&mut self, node: &PatternNode, depth: usize, raw: bool) {
+        let indent = "    ".repeat(depth);
+        match &node.data {
+            PatternData::Document => {
+                for child in node.children.iter() {
+                    self.write(child, depth, raw);
+                }
+            }
+            PatternData::Doctype => *self.out += &format!("{}<!DOCTYPE html>\n", indent),
+            PatternData::Text(text) if raw => *self.out += &format!("{}{}\n", indent, text),
+            PatternData::Text(text) => {
+                *self.out += &format!("{}{}\n", indent, escape_text(text));
+            }
+            PatternData::Comment(text) => *self.out += &format!("{}<!--{}-->\n", indent, text),
+            PatternData::ProcessingInstruction(target, data) => {
+                *self.out += &format!("{}<?{} {}?>\n", indent, target, data);
+            }
+            PatternData::Element(e) => self.write_element(node, e, depth),
+        }
+    }
+
+    fn write_element(&mut self, node: &PatternNode, e: &PatternElement, depth: usize) {
+        let indent = "    ".repeat(depth);
+        let name = qualified(e.name.prefix.as_deref(), &e.name.local);
+
+        *self.out += &format!("{}<{}", indent, name);
+        let saved = self.scope.clone();
+        if self.syntax == Syntax::Xml {
+            self.declare(e.name.prefix.as_deref().unwrap_or(""), &e.name.ns);
+            for (k, v) in e.attributes.map.iter() {
+                if let Some(prefix) = v.prefix.as_deref() {
+                    self.declare(prefix, &k.ns);
+                }
+            }
+        }
+        for (k, v) in e.attributes.map.iter() {
+            let name = qualified(v.prefix.as_deref(), &k.local);
+            if is_special_attr(k) && v.value.is_empty() {
+                *self.out += &format!(" {}", name);
+            } else {
+                *self.out += &format!(" {}=\"{}\"", name, escape_attr(&v.value));
+            }
+        }
+        self.out.push('>');
+
+        if self.syntax == Syntax::Html && is_void_element(&e.name.local) {
+            self.out.push('\n');
+            self.scope = saved;
+            return;
+        }
+
+        let raw = self.syntax == Syntax::Html && is_raw_text_element(&e.name.local);
+        // Put a single text child inline: `<a>{{title}}</a>`
+        match node.children.as_slice() {
+            [] => {}
+            [child] if child.as_text().is_some() => {
+                let text = child.as_text().unwrap();
+                *self.out += &if raw {
+                    text.to_owned()
+                } else {
+                    escape_text(text)
+                };
+            }
+            children => {
+                self.out.push('\n');
+                for child in children {
+                    self.write(child, depth + 1, raw);
+                }
+                *self.out += &indent;
+            }
+        }
+        *self.out += &format!("</{}>\n", name);
+        self.scope = saved;
+    }
+
+    // Declares the namespace of `prefix` if it is not in scope.
+    // Namespaces in `xml::PREFIXES` are declared by the parser.
+    fn declare(&mut self, prefix: &str, ns: &str) {
+        let declared = self
+            .scope
+            .get(prefix)
+            .map(|r| r.as_str())
+            .or_else(|| crate::xml::builtin_namespace(prefix))
+            .unwrap_or("");
+        if declared == ns || prefix == "xml" {
+            return;
+        }
+        if prefix.is_empty() {
+            *self.out += &format!(" xmlns=\"{}\"", escape_attr(ns));
+        } else {
+            *self.out += &format!(" xmlns:{}=\"{}\"", prefix, escape_attr(ns));
+        }
+        self.scope.insert(prefix.to_owned(), ns.to_owned());
+    }
+}
+
+fn qualified(prefix: Option<&str>, local: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}:{}", prefix, local),
+        None => local.to_owned(),
+    }
+}
+
+fn write_tree(f: &mut fmt::Formatter, node: &PatternNode, depth: usize) -> fmt::Result {
+    write!(f, "{}", "    ".repeat(depth))?;
+    match &node.data {
+        PatternData::Document => write!(f, "Document")?,
+        PatternData::Doctype => write!(f, "Doctype")?,
+        PatternData::Element(e) => {
+            write!(
+                f,
+                "Element <{}",
+                qualified(e.name.prefix.as_deref(), &e.name.local)
+            )?;
+            if !e.name.ns.is_empty() && &*e.name.ns != crate::HTML_NS {
+                write!(f, " ns={:?}", &*e.name.ns)?;
+            }
+            let mut special = vec![];
+            for (k, v) in e.attributes.map.iter() {
+                if is_special_attr(k) {
+                    // `fold` is shown for descendants too.
+                    if k.local.as_ref() != "fold" {
+                        special.push(k.local.to_string());
+                    }
+                } else {
+                    write!(
+                        f,
+                        " {}={:?}",
+                        qualified(v.prefix.as_deref(), &k.local),
+                        v.value
+                    )?;
+                }
+            }
+            write!(f, ">")?;
+            if !node.fold.is_empty() {
+                special.push(format!("fold: {}", node.fold));
+            }
+            if !special.is_empty() {
+                write!(f, " ({})", special.join(", "))?;
+            }
+        }
+        PatternData::Text(text) => {
+            if is_skip(text) {
+                write!(f, "Skip")?;
+            } else if let Some(var) = is_var(text) {
+                let kind = if var.whole { "Subtree" } else { "Placeholder" };
+                write!(f, "{} {}", kind, text.trim())?;
+            } else {
+                write!(f, "Text {:?}", text)?;
+            }
+        }
+        PatternData::Comment(text) => write!(f, "Comment {:?}", text)?,
+        PatternData::ProcessingInstruction(target, data) => {
+            write!(f, "ProcessingInstruction {:?} {:?}", target, data)?
+        }
+    }
+    writeln!(f)?;
+
+    for child in node.children.iter() {
+        write_tree(f, child, depth + 1)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn is_void_element(name: &str) -> bool {
+    [
+        "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param",
+        "source", "track", "wbr",
+    ]
+    .contains(&name)
+}
+
+// Elements whose texts are not decoded by the parser.
+fn is_raw_text_element(name: &str) -> bool {
+    [
+        "script",
+        "style",
+        "xmp",
+        "iframe",
+        "noembed",
+        "noframes",
+        "plaintext",
+    ]
+    .contains(&name)
+}
+
+pub(crate) fn escape_text(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub(crate) fn escape_attr(s: &str) -> String {
+    s.replace('&', "&amp;").replace('"', "&quot;")
+}
@@ -0,0 +1,448 @@
+// Streaming matching over `Read` sources.
+// Elements which can be the root of the pattern are kept while parsing, and the others
+// are dropped as soon as they are closed. Candidates are matched when the outermost one
+// is closed, and then dropped too.
+
+use crate::filter::Filters;
+use crate::fuzzy::Fuzzy;
+use crate::pattern::PatternNode;
+use crate::{
+    dedup, document_base, encoding, match_attributes, match_name, new_sink, parse_base_url,
+    Capture, Index, MatchError, MatchOptions, Matcher, Pattern, View,
+};
+use html5ever::tendril::StrTendril;
+use html5ever::tree_builder::{ElementFlags, NodeOrText, QuirksMode, TreeSink};
+use html5ever::{Attribute, ExpandedName, QualName};
+use kuchiki::traits::*;
+use kuchiki::{NodeRef, ParseOpts};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Read;
+use url::Url;
+
+const CHUNK_LEN: usize = 8192;
+
+type Item = Result<BTreeMap<String, String>, MatchError>;
+
+/// Iterator over matches of a document read from `Read`
+///
+/// Created by [`Pattern::matches_reader`].
+pub struct StreamMatches<R> {
+    reader: R,
+    parser: Option<html5ever::Parser<StreamSink>>,
+    decoder: Option<encoding_rs::Decoder>,
+    queue: VecDeque<Item>,
+    max_results: Option<usize>,
+    count: usize,
+}
+
+impl<R: Read> StreamMatches<R> {
+    pub(crate) fn new(
+        pattern: &Pattern,
+        reader: R,
+        opts: &MatchOptions,
+    ) -> Result<StreamMatches<R>, String> {
+        let root = match pattern.roots().as_slice() {
+            [root] if root.as_element().is_some() => (*root).clone(),
+            _ => return Err("Streaming needs a pattern with a single root element".to_owned()),
+        };
+
+        let sink = StreamSink {
+            inner: new_sink(ParseOpts::default()),
+            root,
+            filters: pattern.filters.clone(),
+            fuzzy: pattern.fuzzy,
+            base: parse_base_url(opts).map_err(|e| e.to_string())?,
+            base_found: false,
+            comments: pattern.has_comments(),
+            opts: opts.clone(),
+            open: vec![],
+            queue: VecDeque::new(),
+        };
+
+        Ok(StreamMatches {
+            reader,
+            parser: Some(html5ever::parse_document(sink, Default::default())),
+            decoder: None,
+            queue: VecDeque::new(),
+            max_results: opts.max_results,
+            count: 0,
+        })
+    }
+
+    // Reads the next chunk and feeds it to the parser.
+    fn feed(&mut self) -> Result<(), MatchError> {
+        let mut buf = vec![];
+        let len = if self.decoder.is_none() {
+            // Read enough bytes to find the encoding declaration.
+            (&mut self.reader)
+                .take(encoding::PRESCAN_LEN as u64)
+                .read_to_end(&mut buf)
+        } else {
+            buf.resize(CHUNK_LEN, 0);
+            self.reader.read(&mut buf)
+        };
+        let len = len.map_err(|e| MatchError::Io(e.to_string()))?;
+        buf.truncate(len);
+
+        let decoder = self.decoder.get_or_insert_with(|| {
+            encoding::declared(&buf, None)
+                .unwrap_or(encoding_rs::UTF_8)
+                .new_decoder()
+        });
+
+        let last = len == 0;
+        let mut text = String::with_capacity(decoder.max_utf8_buffer_length(len).unwrap_or(len));
+        let _ = decoder.decode_to_string(&buf, &mut text, last);
+
+        let mut parser = self.parser.take().unwrap();
+        if !text.is_empty() {
+            parser.process(StrTendril::from(text));
+        }
+        if last {
+            self.queue.extend(parser.finish());
+        } else {
+            self.queue
+                .extend(parser.tokenizer.sink.sink.queue.drain(..));
+            self.parser = Some(parser);
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for StreamMatches<R> {
+    type Item = Item;
+
+    fn next(&mut self) -> Option<Item> {
+        loop {
+            if self.max_results.is_some_and(|n| self.count >= n) {
+                return None;
+            }
+
+            if let Some(item) = self.queue.pop_front() {
+                if item.is_err() {
+                    // Stop after errors.
+                    self.parser = None;
+                    self.queue.clear();
+                }
+                self.count += 1;
+                return Some(item);
+            }
+
+            self.parser.as_ref()?;
+            if let Err(err) = self.feed() {
+                self.parser = None;
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+// kuchiki's sink which matches the pattern when candidates of the root are closed.
+struct StreamSink {
+    inner: kuchiki::Sink,
+    root: PatternNode,
+    filters: Filters,
+    fuzzy: Option<Fuzzy>,
+    base: Option<Url>,
+    // `<base href>` is found
+    base_found: bool,
+    comments: bool,
+    opts: MatchOptions,
+    // Candidates which are not closed yet
+    open: Vec<NodeRef>,
+    queue: VecDeque<Item>,
+}
+
+impl StreamSink {
+    fn capture(&self) -> Capture<'_> {
+        Capture {
+            filters: &self.filters,
+            base: self.base.as_ref(),
+        }
+    }
+
+    fn is_candidate(&self, node: &NodeRef) -> bool {
+        match (node.as_element(), self.root.as_element()) {
+            (Some(e1), Some(e2)) => {
+                match_name(&e1.name, &e2.name)
+                    && !match_attributes(
+                        &e1.attributes.borrow(),
+                        &e2.attributes,
+                        self.root.fold,
+                        &self.capture(),
+                    )
+                    .is_empty()
+            }
+            _ => false,
+        }
+    }
+
+    fn match_candidate(&mut self, node: &NodeRef) {
+        let view = View::new(node, &self.opts, self.comments);
+        let index = Index::new(node);
+        let matcher = Matcher::new(
+            &self.opts,
+            self.capture(),
+            self.fuzzy,
+            &self.root,
+            vec![&self.root],
+            view,
+            &index,
+        );
+        let ms = matcher.match_subtree(node, &self.root, false);
+        match ms {
+            Ok(ms) => self.queue.extend(
+                dedup(node, ms, self.opts.dedup)
+                    .into_iter()
+                    .map(|m| Ok(m.bindings)),
+            ),
+            Err(err) => self.queue.push_back(Err(err)),
+        }
+    }
+
+    // Matches candidates which are not contained in the others.
+    fn match_outermost(&mut self, nodes: &[NodeRef]) {
+        for (i, node) in nodes.iter().enumerate() {
+            if !node
+                .ancestors()
+                .any(|r| nodes[..i].contains(&r) || self.open.contains(&r))
+            {
+                self.match_candidate(node);
+                node.detach();
+            }
+        }
+    }
+
+    // Called before a node is appended to `parent`, which is the current node of the parser.
+    // `pop` of the sink is not called for all closed elements, so candidates which do not
+    // contain `parent` are regarded as closed.
+    fn close(&mut self, parent: &NodeRef) {
+        if !is_attached(parent) {
+            return;
+        }
+
+        let (open, closed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.open)
+            .into_iter()
+            .partition(|r| parent.inclusive_ancestors().any(|a| a == *r));
+        self.open = open;
+        self.match_outermost(&closed);
+
+        // Preceding siblings outside of candidates are not needed anymore.
+        if self.open.is_empty() && parent.as_element().is_some() {
+            for child in parent.children().collect::<Vec<_>>() {
+                child.detach();
+            }
+        }
+    }
+
+    fn inserted(&mut self, node: &NodeRef) {
+        if !self.base_found {
+            if let Some(e) = node
+                .as_element()
+                .filter(|e| e.name.local.as_ref() == "base")
+            {
+                if let Some(href) = e.attributes.borrow().get("href") {
+                    self.base_found = true;
+                    self.base = document_base(href, self.base.as_ref()).or(self.base.take());
+                }
+            }
+        }
+
+        if is_attached(node) && self.is_candidate(node) {
+            self.open.push(node.clone());
+        }
+    }
+}
+
+fn is_attached(node: &NodeRef) -> bool {
+    node.inclusive_ancestors()
+        .last()
+        .is_some_and(|r| r.as_document().is_some())
+}
+
+impl TreeSink for StreamSink {
+    type Output = VecDeque<Item>;
+    type Handle = NodeRef;
+
+    fn finish(mut self) -> VecDeque<Item> {
+        // Candidates which are not closed until the end of the document
+        let open = std::mem::take(&mut self.open);
+        self.match_outermost(&open);
+        self.queue
+    }
+
+    fn create_element(
+        &mut self,
+        name: QualName,
+        attrs: Vec<Attribute>,
+        flags: ElementFlags,
+    ) -> NodeRef {
+        self.inner.create_element(name, attrs, flags)
+    }
+
+    fn parse_error(&mut self, msg: Cow<'static, str>) {
+        self.inner.parse_error(msg)
+    }
+
+    fn get_document(&mut self) -> NodeRef {
+        self.inner.get_document()
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.inner.set_quirks_mode(mode)
+    }
+
+    fn same_node(&self, x: &NodeRef, y: &NodeRef) -> bool {
+        self.inner.same_node(x, y)
+    }
+
+    fn elem_name<'a>(&self, target: &'a NodeRef) -> ExpandedName<'a> {
+        target.as_element().unwrap().name.expanded()
+    }
+
+    fn create_comment(&mut self, text: StrTendril) -> NodeRef {
+        self.inner.create_comment(text)
+    }
+
+    fn create_pi(&mut self, target: StrTendril, data: StrTendril) -> NodeRef {
+        self.inner.create_pi(target, data)
+    }
+
+    fn append(&mut self, parent: &NodeRef, child: NodeOrText<NodeRef>) {
+        self.close(parent);
+        let node = appended_node(&child);
+        self.inner.append(parent, child);
+        if let Some(node) = node {
+            self.inserted(&node);
+        }
+    }
+
+    fn append_before_sibling(&mut self, sibling: &NodeRef, child: NodeOrText<NodeRef>) {
+        let node = appended_node(&child);
+        self.inner.append_before_sibling(sibling, child);
+        if let Some(node) = node {
+            self.inserted(&node);
+        }
+    }
+
+    fn append_doctype_to_document(
+        &mut self,
+        name: StrTendril,
+        public_id: StrTendril,
+        system_id: StrTendril,
+    ) {
+        self.inner
+            .append_doctype_to_document(name, public_id, system_id)
+    }
+
+    fn add_attrs_if_missing(&mut self, target: &NodeRef, attrs: Vec<Attribute>) {
+        self.inner.add_attrs_if_missing(target, attrs)
+    }
+
+    fn remove_from_parent(&mut self, target: &NodeRef) {
+        self.inner.remove_from_parent(target)
+    }
+
+    fn reparent_children(&mut self, node: &NodeRef, new_parent: &NodeRef) {
+        self.inner.reparent_children(node, new_parent)
+    }
+
+    fn get_template_contents(&mut self, target: &NodeRef) -> NodeRef {
+        self.inner.get_template_contents(target)
+    }
+
+    fn append_based_on_parent_node(
+        &mut self,
+        element: &NodeRef,
+        prev_element: &NodeRef,
+        child: NodeOrText<NodeRef>,
+    ) {
+        let node = appended_node(&child);
+        self.inner
+            .append_based_on_parent_node(element, prev_element, child);
+        if let Some(node) = node {
+            self.inserted(&node);
+        }
+    }
+}
+
+fn appended_node(child: &NodeOrText<NodeRef>) -> Option<NodeRef> {
+    match child {
+        NodeOrText::AppendNode(node) => Some(node.clone()),
+        NodeOrText::AppendText(_) => None,
+    }
+}
+
+#[test]
+fn test_stream() {
+    // Reader which returns a few bytes at once.
+    struct Slow<'a>(&'a [u8]);
+    impl<'a> Read for Slow<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(7);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    let doc = r#"
+<!DOCTYPE html>
+<html>
+    <body>
+        <div class="item"><a href="/1">One</a></div>
+        <p>noise</p>
+        <div class="item">
+            <a href="/2">Two</a>
+            <div class="item"><a href="/3">Three</a></div>
+        </div>
+        <div class="item"><a href="/4">日本語</a>
+    </body>
+</html>
+"#;
+
+    let pat = Pattern::new(r#"<div class="item"><a href="{{url}}">{{title}}</a></div>"#).unwrap();
+    let opts = MatchOptions::default();
+    let ms = pat
+        .matches_reader_with_options(Slow(doc.as_bytes()), &opts)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(ms.len(), 5);
+    assert_eq!(ms[0]["title"], "One");
+    assert_eq!(ms[4]["title"], "日本語");
+
+    // same matches as non-streaming ones, in order of closing
+    let mut expected = pat.matches(doc);
+    expected.sort();
+    let mut ms = ms;
+    ms.sort();
+    assert_eq!(ms, expected);
+
+    let opts = MatchOptions {
+        max_results: Some(2),
+        ..MatchOptions::default()
+    };
+    let ms = pat
+        .matches_reader_with_options(doc.as_bytes(), &opts)
+        .unwrap();
+    assert_eq!(ms.count(), 2);
+
+    assert!(Pattern::new("<p>{{a}}</p><p>{{b}}</p>")
+        .unwrap()
+        .matches_reader(doc.as_bytes())
+        .is_err());
+
+    // encoding declared after non-ASCII texts
+    let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode(
+        "<title>日本語</title><meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\"><p>本</p>",
+    );
+    let ms = Pattern::new("<p>{{a}}</p>")
+        .unwrap()
+        .matches_reader(Slow(&sjis))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(ms[0]["a"], "本");
+}
@@ -0,0 +1,203 @@
+// Reusable fragments of patterns: `<template name="...">` and `<use ref="...">`.
+// References are expanded in the source of patterns before parsing, so that they can be
+// used anywhere, e.g. in tables, where the parser moves unknown elements out.
+
+use crate::{Pattern, PatternBuilder};
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Named fragments of patterns
+///
+/// Fragments are defined by `<template name="...">`, and referenced by
+/// `<use ref="..."/>` in patterns built with the library.
+/// Placeholders in a fragment are prefixed by the name of the fragment,
+/// or by the `as` attribute of `<use>`.
+///
+/// ```
+/// use easy_scraper::PatternLibrary;
+///
+/// let lib = PatternLibrary::new(r#"
+/// <template name="price"><span class="price">{{amount}}</span></template>
+/// "#).unwrap();
+///
+/// let pat = lib.build(r#"
+/// <div class="item">
+///     <h2>{{title}}</h2>
+///     <use ref="price"/>
+/// </div>
+/// "#).unwrap();
+///
+/// let ms = pat.matches(r#"<div class="item"><h2>Foo</h2><span class="price">100</span></div>"#);
+/// assert_eq!(ms[0]["title"], "Foo");
+/// assert_eq!(ms[0]["price.amount"], "100");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PatternLibrary {
+    templates: HashMap<String, String>,
+}
+
+impl PatternLibrary {
+    /// Create library of templates defined in `src`
+    pub fn new(src: &str) -> Result<PatternLibrary, String> {
+        let mut ret = PatternLibrary::default();
+        ret.add(src)?;
+        Ok(ret)
+    }
+
+    /// Add templates defined in `src`
+    ///
+    /// Templates can reference other templates, which are resolved when patterns are built.
+    pub fn add(&mut self, src: &str) -> Result<(), String> {
+        static RE: OnceLock<Regex> = OnceLock::new();
+        let re = RE
+            .get_or_init(|| Regex::new(r#"(?is)<template(\s[^>]*)?>(.*?)</template\s*>"#).unwrap());
+        for caps in re.captures_iter(src) {
+            let attrs = caps.get(1).map_or("", |r| r.as_str());
+            let name = attribute(attrs, "name").ok_or("Template without name")?;
+            if self.templates.contains_key(&name) {
+                return Err(format!("Duplicate template: \"{}\"", name));
+            }
+            self.templates.insert(name, caps[2].to_owned());
+        }
+        Ok(())
+    }
+
+    /// Build pattern with templates in the same way as [`Pattern::new`]
+    pub fn build(&self, pattern_str: &str) -> Result<Pattern, String> {
+        PatternBuilder::new().library(self).build(pattern_str)
+    }
+
+    // Replaces references in `src` with templates.
+    // Patterns are kept as they are without templates, e.g. for `<use>` of SVG.
+    pub(crate) fn expand(&self, src: &str) -> Result<String, String> {
+        if self.templates.is_empty() {
+            return Ok(src.to_owned());
+        }
+        self.expand_inner(src, &mut vec![])
+    }
+
+    fn expand_inner(&self, src: &str, stack: &mut Vec<String>) -> Result<String, String> {
+        // `<use/>` is not a void element, so `</use>` is also accepted.
+        static RE: OnceLock<Regex> = OnceLock::new();
+        let re = RE.get_or_init(|| Regex::new(r#"(?i)<use(\s[^>]*?)?/?>(\s*</use\s*>)?"#).unwrap());
+        let mut err = None;
+        let ret = re.replace_all(src, |caps: &Captures| {
+            let attrs = caps.get(1).map_or("", |r| r.as_str());
+            let name = match attribute(attrs, "ref") {
+                Some(name) => name,
+                None => return caps[0].to_owned(),
+            };
+            match self.expand_use(&name, attrs, stack) {
+                Ok(s) => s,
+                Err(e) => {
+                    err.get_or_insert(e);
+                    String::new()
+                }
+            }
+        });
+        match err {
+            Some(err) => Err(err),
+            None => Ok(ret.into_owned()),
+        }
+    }
+
+    fn expand_use(
+        &self,
+        name: &str,
+        attrs: &str,
+        stack: &mut Vec<String>,
+    ) -> Result<String, String> {
+        let src = self
+            .templates
+            .get(name)
+            .ok_or_else(|| format!("Unknown template: \"{}\"", name))?;
+        if stack.iter().any(|r| r == name) {
+            stack.push(name.to_owned());
+            return Err(format!("Recursive template: {}", stack.join(" -> ")));
+        }
+
+        stack.push(name.to_owned());
+        let expanded = self.expand_inner(src, stack)?;
+        stack.pop();
+
+        let prefix = attribute(attrs, "as").unwrap_or_else(|| name.to_owned());
+        Ok(prefix_placeholders(&expanded, &prefix))
+    }
+}
+
+// Value of attribute `name` in the attributes part of a tag.
+fn attribute(attrs: &str, name: &str) -> Option<String> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| {
+        Regex::new(r#"(?i)(?:^|\s)([\w-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap()
+    });
+    let caps = re
+        .captures_iter(attrs)
+        .find(|caps| caps[1].eq_ignore_ascii_case(name))?;
+    let value = caps.get(2).or(caps.get(3)).or(caps.get(4))?;
+    Some(value.as_str().to_owned())
+}
+
+// `{{amount|number}}` to `{{price.amount|number}}`
+fn prefix_placeholders(s: &str, prefix: &str) -> String {
+    let mut ret = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find("{{") {
+        let close = match rest[i..].find("}}") {
+            Some(close) => i + close,
+            None => break,
+        };
+        let inner = &rest[i + 2..close];
+        ret += &rest[..i + 2];
+        if !inner.trim().is_empty() {
+            ret += prefix;
+            ret.push('.');
+        }
+        ret += inner;
+        ret += "}}";
+        rest = &rest[close + 2..];
+    }
+    ret + rest
+}
+
+#[test]
+fn test_template() {
+    let lib = PatternLibrary::new(
+        r#"
+<template name="money"><b>{{value|number}}</b></template>
+<TEMPLATE name='price'><span class="price"><use ref="money" as="amount"></use></span></TEMPLATE>
+<template name="a"><use ref="b"/></template>
+<template name="b"><i><use ref="a"/></i></template>
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        lib.expand(r#"<tr><td><use ref="price"/></td></tr>"#)
+            .unwrap(),
+        r#"<tr><td><span class="price"><b>{{price.amount.value|number}}</b></span></td></tr>"#
+    );
+    assert_eq!(
+        lib.expand(r#"<use ref="money" as="m" /><use ref=money>"#)
+            .unwrap(),
+        r#"<b>{{m.value|number}}</b><b>{{money.value|number}}</b>"#
+    );
+    assert_eq!(
+        lib.expand(r#"<div><use ref="a"/></div>"#).unwrap_err(),
+        "Recursive template: a -> b -> a"
+    );
+    assert_eq!(
+        lib.expand(r#"<use ref="c"/>"#).unwrap_err(),
+        "Unknown template: \"c\""
+    );
+    assert_eq!(
+        lib.expand(r##"<svg><use href="#a"/></svg>"##).unwrap(),
+        r##"<svg><use href="#a"/></svg>"##
+    );
+    assert!(PatternLibrary::new(r#"<template>x</template>"#).is_err());
+    assert!(
+        PatternLibrary::new(r#"<template name="x"></template><template name="x"></template>"#)
+            .is_err()
+    );
+}
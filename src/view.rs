@@ -0,0 +1,142 @@
+// View of documents for matching.
+// White-space only texts and comments are skipped, and texts are normalized
+// when they are read, instead of copying the whole tree.
+
+use crate::{node_id, normalize_whitespace, MatchOptions, WhitespaceMode};
+use html5ever::serialize::{serialize, Serialize, SerializeOpts, Serializer, TraversalScope};
+use html5ever::QualName;
+use kuchiki::{Node, NodeData, NodeRef};
+use std::collections::HashSet;
+use std::io;
+
+pub(crate) struct View {
+    mode: WhitespaceMode,
+    // Show comments and processing instructions
+    comments: bool,
+    // Text nodes in elements whose texts are kept as they are
+    preserved: HashSet<*const Node>,
+}
+
+impl View {
+    pub(crate) fn new(root: &NodeRef, opts: &MatchOptions, comments: bool) -> View {
+        let is_preserve = |node: &NodeRef| {
+            node.as_element()
+                .is_some_and(|e| opts.preserve_elements.iter().any(|r| *r == *e.name.local))
+        };
+
+        let mut preserved = HashSet::new();
+        let mut add_texts = |node: &NodeRef| {
+            for text in node.descendants().filter(|r| r.as_text().is_some()) {
+                preserved.insert(node_id(&text));
+            }
+        };
+        if root.ancestors().any(|r| is_preserve(&r)) {
+            add_texts(root);
+        } else {
+            for node in root.inclusive_descendants().filter(is_preserve) {
+                add_texts(&node);
+            }
+        }
+
+        View {
+            mode: opts.whitespace,
+            comments,
+            preserved,
+        }
+    }
+
+    pub(crate) fn children(&self, node: &NodeRef) -> Vec<NodeRef> {
+        node.children().filter(|r| self.is_visible(r)).collect()
+    }
+
+    fn is_visible(&self, node: &NodeRef) -> bool {
+        match node.data() {
+            NodeData::Text(text) => {
+                // White-space only nodes are removed even in `Preserve` mode
+                self.preserved.contains(&node_id(node)) || !is_blank(&text.borrow(), self.mode)
+            }
+            NodeData::Comment(_) | NodeData::ProcessingInstruction(_) => self.comments,
+            _ => true,
+        }
+    }
+
+    // Normalized content of text node.
+    pub(crate) fn text(&self, node: &NodeRef) -> Option<String> {
+        let text = node.as_text()?.borrow();
+        if self.preserved.contains(&node_id(node)) {
+            Some(text.clone())
+        } else {
+            Some(normalize_whitespace(&text, self.mode))
+        }
+    }
+
+    // Serializes visible nodes of the subtree.
+    pub(crate) fn to_html(&self, node: &NodeRef) -> String {
+        let mut ret = vec![];
+        let opts = SerializeOpts {
+            traversal_scope: TraversalScope::IncludeNode,
+            ..SerializeOpts::default()
+        };
+        serialize(&mut ret, &Visible { view: self, node }, opts).unwrap();
+        String::from_utf8(ret).unwrap()
+    }
+}
+
+fn is_blank(s: &str, mode: WhitespaceMode) -> bool {
+    match mode {
+        WhitespaceMode::Preserve | WhitespaceMode::Trim => s.trim().is_empty(),
+        WhitespaceMode::Collapse => s.bytes().all(|b| b.is_ascii_whitespace()),
+        WhitespaceMode::Normalize => s
+            .chars()
+            .all(|c| c.is_whitespace() || crate::is_zero_width(c)),
+    }
+}
+
+struct Visible<'a> {
+    view: &'a View,
+    node: &'a NodeRef,
+}
+
+impl<'a> Serialize for Visible<'a> {
+    fn serialize<S: Serializer>(&self, serializer: &mut S, _: TraversalScope) -> io::Result<()> {
+        let children = |serializer: &mut S| {
+            for child in self.view.children(self.node) {
+                let child = Visible {
+                    view: self.view,
+                    node: &child,
+                };
+                child.serialize(serializer, TraversalScope::IncludeNode)?;
+            }
+            Ok(())
+        };
+
+        match self.node.data() {
+            NodeData::Element(element) => {
+                let attrs = element.attributes.borrow();
+                let attrs = attrs
+                    .map
+                    .iter()
+                    .map(|(name, attr)| {
+                        let name =
+                            QualName::new(attr.prefix.clone(), name.ns.clone(), name.local.clone());
+                        (name, attr.value.as_str())
+                    })
+                    .collect::<Vec<_>>();
+                serializer.start_elem(
+                    element.name.clone(),
+                    attrs.iter().map(|(name, value)| (name, *value)),
+                )?;
+                children(serializer)?;
+                serializer.end_elem(element.name.clone())
+            }
+            NodeData::Document(_) | NodeData::DocumentFragment => children(serializer),
+            NodeData::Doctype(doctype) => serializer.write_doctype(&doctype.name),
+            NodeData::Text(_) => serializer.write_text(&self.view.text(self.node).unwrap()),
+            NodeData::Comment(text) => serializer.write_comment(&text.borrow()),
+            NodeData::ProcessingInstruction(pi) => {
+                let pi = pi.borrow();
+                serializer.write_processing_instruction(&pi.0, &pi.1)
+            }
+        }
+    }
+}
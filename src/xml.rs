@@ -0,0 +1,104 @@
+// XML parsing mode.
+// Documents are parsed by roxmltree and converted to the same tree as HTML documents,
+// keeping namespaces and cases of names.
+
+use html5ever::{Namespace, Prefix, QualName};
+use kuchiki::{Attribute, ExpandedName, NodeRef};
+use roxmltree::NodeType;
+
+// Namespace prefixes which can be used in patterns without declarations.
+const PREFIXES: &[(&str, &str)] = &[
+    ("atom", "http://www.w3.org/2005/Atom"),
+    ("content", "http://purl.org/rss/1.0/modules/content/"),
+    ("dc", "http://purl.org/dc/elements/1.1/"),
+    ("media", "http://search.yahoo.com/mrss/"),
+    ("sitemap", "http://www.sitemaps.org/schemas/sitemap/0.9"),
+    ("svg", "http://www.w3.org/2000/svg"),
+    ("xhtml", "http://www.w3.org/1999/xhtml"),
+    ("xlink", "http://www.w3.org/1999/xlink"),
+];
+
+// Namespace of a prefix which can be used without declarations.
+pub(crate) fn builtin_namespace(prefix: &str) -> Option<&'static str> {
+    PREFIXES
+        .iter()
+        .find(|(r, _)| *r == prefix)
+        .map(|(_, ns)| *ns)
+}
+
+// Name of the element which wraps patterns, to allow multiple root nodes.
+const PATTERN_ROOT: &str = "easy-scraper-pattern";
+
+pub(crate) fn parse_xml(s: &str) -> Result<NodeRef, String> {
+    let opts = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..roxmltree::ParsingOptions::default()
+    };
+    let doc = roxmltree::Document::parse_with_options(s, opts).map_err(|e| e.to_string())?;
+
+    let ret = NodeRef::new_document();
+    for child in doc.root().children() {
+        ret.append(convert(child));
+    }
+    Ok(ret)
+}
+
+// Parses XML pattern. Returned document has root nodes of the pattern as its children.
+pub(crate) fn parse_xml_pattern(s: &str) -> Result<NodeRef, String> {
+    let decls = PREFIXES
+        .iter()
+        .map(|(prefix, ns)| format!(" xmlns:{}=\"{}\"", prefix, ns))
+        .collect::<String>();
+    let src = format!("<{}{}>{}</{}>", PATTERN_ROOT, decls, s, PATTERN_ROOT);
+
+    let wrapped = parse_xml(&src)?;
+    let ret = NodeRef::new_document();
+    for root in wrapped.children() {
+        for child in root.children().collect::<Vec<_>>() {
+            child.detach();
+            ret.append(child);
+        }
+    }
+    Ok(ret)
+}
+
+fn convert(node: roxmltree::Node) -> NodeRef {
+    match node.node_type() {
+        NodeType::Root | NodeType::Element => {
+            let tag = node.tag_name();
+            let ns = tag.namespace().unwrap_or("");
+            let prefix = tag
+                .namespace()
+                .and_then(|ns| node.lookup_prefix(ns))
+                .map(Prefix::from);
+            let name = QualName::new(prefix, Namespace::from(ns), tag.name().into());
+
+            let attrs = node.attributes().map(|attr| {
+                let ns = attr.namespace().unwrap_or("");
+                let prefix = attr
+                    .namespace()
+                    .and_then(|ns| node.lookup_prefix(ns))
+                    .map(Prefix::from);
+                (
+                    ExpandedName::new(ns, attr.name()),
+                    Attribute {
+                        prefix,
+                        value: attr.value().to_owned(),
+                    },
+                )
+            });
+
+            let ret = NodeRef::new_element(name, attrs);
+            for child in node.children() {
+                ret.append(convert(child));
+            }
+            ret
+        }
+        NodeType::Text => NodeRef::new_text(node.text().unwrap_or("")),
+        NodeType::Comment => NodeRef::new_comment(node.text().unwrap_or("")),
+        NodeType::PI => {
+            let pi = node.pi().unwrap();
+            NodeRef::new_processing_instruction(pi.target, pi.value.unwrap_or(""))
+        }
+    }
+}